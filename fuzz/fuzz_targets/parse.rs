@@ -0,0 +1,29 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use brainfuck_riscv::{compiler, diagnostics, instruction::Instruction, parser::parse};
+
+// Any input either parses into a program whose loops are linked to each other, which can then
+// be compiled, or produces diagnostics
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    match parse(&source) {
+        Ok(program) => {
+            for (index, instruction) in program.iter().enumerate() {
+                match *instruction {
+                    Instruction::LoopStart { end } => {
+                        assert_eq!(program[end], Instruction::LoopEnd { start: index })
+                    }
+                    Instruction::LoopEnd { start } => {
+                        assert_eq!(program[start], Instruction::LoopStart { end: index })
+                    }
+                    _ => {}
+                }
+            }
+            compiler::compile_risc_v(&program);
+        }
+        Err(error) => {
+            assert!(!diagnostics::from_parse_error(&error, &source).is_empty());
+        }
+    }
+});
@@ -0,0 +1,47 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryInto;
+
+use brainfuck_riscv::{
+    compiler,
+    dialect::{CellWidth, Dialect},
+    difftest::{Case, Harness},
+    generator::{self, Config},
+    optimizer::{OptLevel, PassManager},
+};
+
+// The first 8 bytes seed the program generator and choose the target and cell width, and the
+// rest are the program's input. The optimized program and its assembly must behave like the
+// original. Generated programs only terminate with 8-bit and 16-bit cells.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let (seed, input) = data.split_at(8);
+    let seed = u64::from_le_bytes(seed.try_into().unwrap());
+
+    let target = if seed & 1 == 0 { "riscv32" } else { "riscv64" };
+    let cell_width = if seed & 4 == 0 {
+        CellWidth::Bits8
+    } else {
+        CellWidth::Bits16
+    };
+    let dialect = Dialect {
+        cell_width,
+        ..Dialect::default()
+    };
+    let mut backend = compiler::backend(target, dialect).unwrap();
+    if seed & 2 != 0 {
+        backend.set_option("buffered-io", "true").unwrap();
+    }
+
+    let case = Case {
+        name: format!("seed {}", seed),
+        source: generator::generate(seed, Config::default()),
+        input: input.to_vec(),
+    };
+    let mut harness = Harness::new(dialect, PassManager::new(OptLevel::O2), backend);
+    if let Err(failure) = harness.check(&case) {
+        panic!("{}\n{}", failure, case.source);
+    }
+});
@@ -1,75 +1,93 @@
-use crate::instruction::Instruction;
+use std::{error::Error, fmt};
 
-/// Compile the given program into risc v assembly
-pub fn compile_risc_v(program: &[Instruction]) -> String {
-    let mut output = String::new();
+use crate::{dialect::Dialect, instruction::Instruction};
 
-    // Generate code to allocate the 30KB memory space
-    output.push_str(".data\n");
-    output.push_str("memory: .space 30000\n\n");
+pub mod riscv;
 
-    // Register s0 will be our pointer. Set it to point to the beginning of memory
-    output.push_str(".text\n");
-    output.push_str("main:\n");
-    output.push_str("la s0, memory\n");
+/// The names of the targets that code can be generated for, the first being the default
+pub const TARGETS: &[&str] = &["riscv32", "riscv64"];
 
-    // Generate assembly for each instruction
-    for (index, inst) in program.iter().enumerate() {
-        match inst {
-            Instruction::AddPtr { count } => {
-                output.push_str(&format!("addi s0, s0, {}\n\n", count));
-            }
-            Instruction::SubPtr { count } => {
-                output.push_str(&format!("addi s0, s0, {}\n\n", -(*count as i64)));
-            }
-            Instruction::AddByte { count } => {
-                output.push_str("lbu s1, (s0)\n");
-                output.push_str(&format!("addi s1, s1, {}\n", count));
-                output.push_str("sb s1, (s0)\n\n");
-            }
-            Instruction::SubByte { count } => {
-                output.push_str("lbu s1, (s0)\n");
-                output.push_str(&format!("addi s1, s1, {}\n", -(*count as i64)));
-                output.push_str("sb s1, (s0)\n\n");
-            }
-            Instruction::Read { count } => {
-                output.push_str("li a7, 12\n");
+/// A code generator for a particular target
+pub trait Backend {
+    /// The name used to select this backend with `--target`
+    fn name(&self) -> &'static str;
 
-                for _ in 0..*count {
-                    output.push_str("ecall\n");
-                }
+    /// Set a target-specific option by name, such as from `--target-option <name>=<value>`
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError>;
 
-                output.push_str("sb a0, (s0)\n\n")
-            }
-            Instruction::Write { count } => {
-                output.push_str("lbu a0, (s0)\n");
-                output.push_str("li a7, 11\n");
+    /// Check that the backend can generate code with its current options
+    fn validate(&self) -> Result<(), OptionError>;
 
-                for _ in 0..*count {
-                    output.push_str("ecall\n");
-                }
+    /// Generate code that comes before the program, such as memory allocation and setup
+    fn prologue(&mut self, output: &mut String);
 
-                output.push('\n');
-            }
-            Instruction::LoopStart { end } => {
-                output.push_str("lbu s1, (s0)\n");
-                output.push_str(&format!("bnez s1, start_{}\n", index));
-                output.push_str(&format!("j end_{}\n", end));
-                output.push_str(&format!("start_{}:\n\n", index));
-            }
-            Instruction::LoopEnd { start } => {
-                output.push_str("lbu s1, (s0)\n");
-                output.push_str(&format!("beqz s1, end_{}\n", index));
-                output.push_str(&format!("j start_{}\n", start));
-                output.push_str(&format!("end_{}:\n\n", index));
+    /// Generate code for the instruction at `index` in the program
+    fn instruction(&mut self, program: &[Instruction], index: usize, output: &mut String);
+
+    /// Generate code that comes after the program, such as exiting
+    fn epilogue(&mut self, output: &mut String);
+}
+
+/// Constructs the backend for the target with the given name, if there is one
+pub fn backend(target: &str, dialect: Dialect) -> Option<Box<dyn Backend>> {
+    match target {
+        "riscv32" => Some(Box::new(riscv::RiscV::rv32(dialect))),
+        "riscv64" => Some(Box::new(riscv::RiscV::rv64(dialect))),
+        _ => None,
+    }
+}
+
+/// Compile the given program with the given backend
+pub fn compile(backend: &mut dyn Backend, program: &[Instruction]) -> String {
+    let mut output = String::new();
+
+    backend.prologue(&mut output);
+    for index in 0..program.len() {
+        backend.instruction(program, index, &mut output);
+    }
+    backend.epilogue(&mut output);
+
+    output
+}
+
+/// Compile the given program into risc v assembly
+pub fn compile_risc_v(program: &[Instruction]) -> String {
+    compile(&mut riscv::RiscV::rv32(Dialect::default()), program)
+}
+
+/// The error returned when a target option cannot be set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    /// The target has no option with this name
+    Unknown(String),
+    /// The value is not valid for the option
+    InvalidValue { name: String, value: String },
+    /// The options are valid individually, but the target does not support them together
+    Unsupported(String),
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionError::Unknown(name) => write!(f, "unknown target option `{}`", name),
+            OptionError::InvalidValue { name, value } => {
+                write!(f, "invalid value for target option `{}`: `{}`", name, value)
             }
+            OptionError::Unsupported(message) => write!(f, "{}", message),
         }
     }
+}
 
-    // Generate code to exit
-    output.push_str("li	a0, 0\n");
-	output.push_str("li 	a7, 93\n");
-	output.push_str("ecall\n\n");
+impl Error for OptionError {}
 
-    output
+/// Parses the value of a boolean target option
+fn parse_bool(name: &str, value: &str) -> Result<bool, OptionError> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(OptionError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
 }
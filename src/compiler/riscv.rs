@@ -0,0 +1,1104 @@
+use std::collections::HashSet;
+
+use crate::{
+    dialect::{CellWidth, Dialect, EofBehavior},
+    instruction::Instruction,
+    isa::{Arch, Xlen, INSTRUCTION_LENGTH},
+    riscv_sim::assembler,
+};
+
+use super::{parse_bool, Backend, OptionError};
+
+/// The smallest immediate accepted by `addi`, a signed 12-bit value
+const IMMEDIATE_MIN: i64 = -2048;
+
+/// The largest immediate accepted by `addi`, a signed 12-bit value
+const IMMEDIATE_MAX: i64 = 2047;
+
+/// The farthest in bytes that a conditional branch can reach in either direction, which is
+/// 4 KiB backwards but 2 bytes less forwards
+const BRANCH_RANGE: u64 = 4094;
+
+/// The environment that the generated code runs in, which determines how it makes system calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    /// The RARS and Venus simulators, using their PrintChar and ReadChar environment calls
+    Rars,
+    /// Linux user space, using the `read`, `write`, and `exit` system calls.
+    /// The output can be assembled with GNU binutils.
+    Linux,
+    /// A function that can be called from C or Rust, following the standard calling convention:
+    ///
+    /// `int bf_main(void *tape, size_t length, const struct bf_io *io)`
+    ///
+    /// The tape is `length` cells long, and must be zeroed by the caller. `struct bf_io` holds the
+    /// pointers `int (*read)(void *context)`, which returns -1 at the end of input, and
+    /// `void (*write)(void *context, int byte)`, followed by the `void *context` passed to them.
+    /// The function returns 0, or the exit code of a failed bounds check.
+    Library,
+}
+
+/// The name of the function generated by the library runtime
+pub const LIBRARY_FUNCTION: &str = "bf_main";
+
+/// The registers that the library runtime's function uses and must restore before returning.
+/// s7 holds the address of the `bf_io` structure.
+const SAVED_REGISTERS: [&str; 6] = ["ra", "s0", "s1", "s5", "s6", "s7"];
+
+/// The indexes of the fields of `struct bf_io`, which are each the size of a register
+const IO_READ: i64 = 0;
+const IO_WRITE: i64 = 1;
+const IO_CONTEXT: i64 = 2;
+
+/// Linux system call numbers
+const SYS_READ: u32 = 63;
+const SYS_WRITE: u32 = 64;
+const SYS_EXIT: u32 = 93;
+
+/// The sizes in bytes of the buffers used for buffered I/O
+const OUTPUT_BUFFER_SIZE: usize = 4096;
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+/// The exit codes of bounds checked programs that move the pointer off either end of the tape
+pub const UNDERFLOW_EXIT_CODE: i32 = 3;
+pub const OVERFLOW_EXIT_CODE: i32 = 4;
+
+/// RARS environment call numbers
+const RARS_PRINT_CHAR: u32 = 11;
+const RARS_READ_CHAR: u32 = 12;
+
+/// Generates RISC-V assembly
+#[derive(Debug, Clone)]
+pub struct RiscV {
+    /// The ISA that code is generated for. RV64 can search for zero cells a 64-bit word at a
+    /// time, and the M extension provides the `mul` instruction.
+    pub arch: Arch,
+    /// The environment that the generated code runs in
+    pub runtime: Runtime,
+    /// Whether to buffer input and output instead of making a system call for every byte
+    pub buffered_io: bool,
+    /// Whether to exit with an error when the program accesses a cell off either end of the tape
+    pub bounds_check: bool,
+    /// Whether the assembler may use 16-bit compressed instructions, which requires the C
+    /// extension. The pointer and current cell are kept in s0 and s1, which most compressed
+    /// instructions can use, and loops test s1 so that their branches can be compressed.
+    /// Byte loads and stores have no compressed form, so wider cells benefit the most.
+    pub compressed: bool,
+    /// Whether to keep the current cell in s1 between instructions, only storing it to memory
+    /// when the pointer moves, for I/O, and before loop tests, where control flow merges
+    pub cache_cell: bool,
+    /// The layout of memory
+    pub dialect: Dialect,
+    /// The bounds check failures that the generated code can jump to, which are generated at the end
+    bounds_errors: Vec<BoundsError>,
+    /// The indexes of the loops whose start and end are too far apart for a single branch
+    far_loops: HashSet<usize>,
+    /// What s1 holds at this point in the generated code, when the current cell is cached
+    cell: CachedCell,
+}
+
+/// The state of the current cell cached in s1
+#[derive(Debug, Clone, Copy, Default)]
+struct CachedCell {
+    /// Whether s1 holds the value of the current cell
+    valid: bool,
+    /// Whether s1 has changed since the current cell was last stored
+    dirty: bool,
+    /// Whether s1 may have bits set above the width of a cell, which must be cleared before it
+    /// is compared with zero
+    overflowed: bool,
+}
+
+impl CachedCell {
+    /// s1 holds the current cell, which is the same as in memory
+    const CLEAN: CachedCell = CachedCell {
+        valid: true,
+        dirty: false,
+        overflowed: false,
+    };
+}
+
+/// Code that reports a failed bounds check and exits
+#[derive(Debug, Clone)]
+struct BoundsError {
+    /// The label that bounds checks jump to on failure
+    label: String,
+    /// The message to print
+    message: String,
+    /// The exit code
+    code: i32,
+}
+
+impl RiscV {
+    /// Constructs a backend for RV32IM
+    pub fn rv32(dialect: Dialect) -> Self {
+        RiscV {
+            arch: Arch::default_for(Xlen::Rv32),
+            runtime: Runtime::Rars,
+            buffered_io: false,
+            bounds_check: false,
+            compressed: false,
+            cache_cell: false,
+            dialect,
+            bounds_errors: Vec::new(),
+            far_loops: HashSet::new(),
+            cell: CachedCell::default(),
+        }
+    }
+
+    /// Constructs a backend for RV64GC
+    pub fn rv64(dialect: Dialect) -> Self {
+        RiscV {
+            arch: Arch::default_for(Xlen::Rv64),
+            ..RiscV::rv32(dialect)
+        }
+    }
+
+    /// Whether the target is RV64
+    fn is_rv64(&self) -> bool {
+        self.arch.xlen == Xlen::Rv64
+    }
+
+    /// The instruction that loads a cell, zero-extending it if it is narrower than a register,
+    /// except that `lw` sign-extends 32-bit cells on RV64. That only changes the upper bits, which
+    /// are never stored, and the register is still zero exactly when the cell is, so `beqz`,
+    /// `bnez`, and `sw` behave the same. Unlike `lwu`, `lw` also has a compressed form.
+    fn load(&self) -> &'static str {
+        match self.dialect.cell_width {
+            CellWidth::Bits8 => "lbu",
+            CellWidth::Bits16 => "lhu",
+            CellWidth::Bits32 => "lw",
+            CellWidth::Bits64 => "ld",
+        }
+    }
+
+    /// The instruction that stores a cell
+    fn store(&self) -> &'static str {
+        match self.dialect.cell_width {
+            CellWidth::Bits8 => "sb",
+            CellWidth::Bits16 => "sh",
+            CellWidth::Bits32 => "sw",
+            CellWidth::Bits64 => "sd",
+        }
+    }
+
+    /// Converts a distance in cells to a distance in bytes
+    fn bytes(&self, cells: isize) -> i64 {
+        cells as i64 * self.dialect.cell_width.bytes() as i64
+    }
+
+    /// The size of a register in bytes
+    fn register_bytes(&self) -> i64 {
+        if self.is_rv64() {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// The instructions that load and store a whole register
+    fn load_register(&self) -> &'static str {
+        if self.is_rv64() {
+            "ld"
+        } else {
+            "lw"
+        }
+    }
+
+    fn store_register(&self) -> &'static str {
+        if self.is_rv64() {
+            "sd"
+        } else {
+            "sw"
+        }
+    }
+
+    /// The size of the library runtime's stack frame, which the calling convention requires to
+    /// be a multiple of 16 bytes
+    fn frame_size(&self) -> i64 {
+        let size = SAVED_REGISTERS.len() as i64 * self.register_bytes();
+        (size + 15) & !15
+    }
+
+    /// Generate code to call the `bf_io` callback with the given field index, passing it the
+    /// context in a0. Any other arguments must already be in place.
+    fn callback(&self, output: &mut String, field: i64) {
+        let load = self.load_register();
+        let size = self.register_bytes();
+        output.push_str(&format!("{} a0, {}(s7)\n", load, IO_CONTEXT * size));
+        output.push_str(&format!("{} t0, {}(s7)\n", load, field * size));
+        output.push_str("jalr t0\n");
+    }
+
+    /// Generate code to read `count` bytes into the current cell, using the labels
+    /// `read_eof_{index}` and `read_end_{index}`. Once the end of input is reached, the
+    /// remaining reads are skipped and the cell is updated according to the EOF behavior.
+    fn read(&self, output: &mut String, index: usize, count: usize) {
+        let eof_label = if self.dialect.eof == EofBehavior::Unchanged {
+            format!("read_end_{}", index)
+        } else {
+            format!("read_eof_{}", index)
+        };
+
+        for _ in 0..count {
+            if self.buffered_io {
+                output.push_str("jal __bf_read\n");
+                output.push_str(&format!("bltz a0, {}\n", eof_label));
+                output.push_str(&format!("{} a0, (s0)\n", self.store()));
+                continue;
+            }
+
+            match self.runtime {
+                Runtime::Rars => {
+                    output.push_str(&format!("li a7, {}\n", RARS_READ_CHAR));
+                    output.push_str("ecall\n");
+                    output.push_str(&format!("bltz a0, {}\n", eof_label));
+                    output.push_str(&format!("{} a0, (s0)\n", self.store()));
+                }
+                Runtime::Linux => {
+                    // Read directly into the lowest byte of the current cell,
+                    // then clear the rest of a wider cell
+                    linux_syscall(output, SYS_READ, 0);
+                    output.push_str(&format!("blez a0, {}\n", eof_label));
+                    if self.dialect.cell_width != CellWidth::Bits8 {
+                        output.push_str("lbu t0, (s0)\n");
+                        output.push_str(&format!("{} t0, (s0)\n", self.store()));
+                    }
+                }
+                Runtime::Library => {
+                    self.callback(output, IO_READ);
+                    output.push_str(&format!("bltz a0, {}\n", eof_label));
+                    output.push_str(&format!("{} a0, (s0)\n", self.store()));
+                }
+            }
+        }
+
+        match self.dialect.eof {
+            EofBehavior::Unchanged => {}
+            EofBehavior::Zero => {
+                output.push_str(&format!("j read_end_{}\n", index));
+                output.push_str(&format!("{}:\n", eof_label));
+                output.push_str(&format!("{} zero, (s0)\n", self.store()));
+            }
+            EofBehavior::MinusOne => {
+                output.push_str(&format!("j read_end_{}\n", index));
+                output.push_str(&format!("{}:\n", eof_label));
+                output.push_str("li t0, -1\n");
+                output.push_str(&format!("{} t0, (s0)\n", self.store()));
+            }
+        }
+        output.push_str(&format!("read_end_{}:\n\n", index));
+    }
+
+    /// Generate code to check that the cells accessed by the instructions starting at `start`
+    /// are on the tape. The checked instructions run until the pointer next moves or a loop
+    /// starts or ends, each of which begins a new check.
+    fn check_region(&mut self, program: &[Instruction], start: usize, output: &mut String) {
+        // The lowest and highest offsets accessed, and the instructions that access them
+        let mut lowest: Option<(isize, usize)> = None;
+        let mut highest: Option<(isize, usize)> = None;
+        let mut access = |offset: isize, index: usize| {
+            if lowest.is_none_or(|(lowest, _)| offset < lowest) {
+                lowest = Some((offset, index));
+            }
+            if highest.is_none_or(|(highest, _)| offset > highest) {
+                highest = Some((offset, index));
+            }
+        };
+
+        // The pointer may leave the tape as long as no cell is accessed there, so only the cells
+        // that the region accesses are checked. If the pointer did not just move, the cell it
+        // points to was already checked, by the loop test or scan that ended the last region.
+        let moved = start == 0
+            || matches!(
+                program[start - 1],
+                Instruction::AddPtr { .. } | Instruction::SubPtr { .. }
+            );
+
+        for (index, inst) in program.iter().enumerate().skip(start) {
+            match inst {
+                Instruction::AddByte { offset, .. } | Instruction::SetByte { offset, .. } => {
+                    access(*offset, index)
+                }
+                Instruction::MulAdd { offset, .. } => {
+                    access(0, index);
+                    access(*offset, index);
+                }
+                Instruction::Read { .. } | Instruction::Write { .. } => access(0, index),
+                Instruction::LoopStart { .. }
+                | Instruction::LoopEnd { .. }
+                | Instruction::Scan { .. } => {
+                    access(0, index);
+                    break;
+                }
+                Instruction::AddPtr { .. } | Instruction::SubPtr { .. } => break,
+            }
+        }
+
+        if let (Some(lowest), Some(highest)) = (lowest, highest) {
+            if moved || lowest.0 != 0 || highest.0 != 0 {
+                let label = start.to_string();
+                self.check_range(output, &label, lowest, highest);
+            }
+        }
+    }
+
+    /// Generate code to check that the cells between the `lowest` and `highest` offsets from
+    /// the pointer are on the tape, along with the instructions that access them.
+    /// The labels used are suffixed with `label`.
+    fn check_range(
+        &mut self,
+        output: &mut String,
+        label: &str,
+        lowest: (isize, usize),
+        highest: (isize, usize),
+    ) {
+        // s5 holds the address of the first cell, and s6 the address of the last cell
+        add_immediate_into(output, "t0", "s0", self.bytes(lowest.0));
+        output.push_str(&format!("bgeu t0, s5, bounds_low_{}\n", label));
+        output.push_str(&format!("j bounds_underflow_{}\n", label));
+        output.push_str(&format!("bounds_low_{}:\n", label));
+        add_immediate_into(output, "t0", "s0", self.bytes(highest.0));
+        output.push_str(&format!("bleu t0, s6, bounds_high_{}\n", label));
+        output.push_str(&format!("j bounds_overflow_{}\n", label));
+        output.push_str(&format!("bounds_high_{}:\n\n", label));
+
+        self.bounds_errors.push(BoundsError {
+            label: format!("bounds_underflow_{}", label),
+            message: format!("pointer underflow at instruction {}\n", lowest.1),
+            code: UNDERFLOW_EXIT_CODE,
+        });
+        self.bounds_errors.push(BoundsError {
+            label: format!("bounds_overflow_{}", label),
+            message: format!("pointer overflow at instruction {}\n", highest.1),
+            code: OVERFLOW_EXIT_CODE,
+        });
+    }
+
+    /// Generate the code that each failed bounds check jumps to, which passes a message and
+    /// exit code to `__bf_bounds_error`. The library runtime returns the exit code instead.
+    fn bounds_error_runtime(&self, output: &mut String) {
+        if self.runtime == Runtime::Library {
+            for error in &self.bounds_errors {
+                output.push_str(&format!("{}:\n", error.label));
+                output.push_str(&format!("li a0, {}\n", error.code));
+                output.push_str("j __bf_return\n");
+            }
+            output.push('\n');
+            return;
+        }
+
+        for (index, error) in self.bounds_errors.iter().enumerate() {
+            output.push_str(&format!("{}:\n", error.label));
+            output.push_str(&format!("la a1, bounds_message_{}\n", index));
+            output.push_str(&format!("li a2, {}\n", error.message.len()));
+            output.push_str(&format!("li a3, {}\n", error.code));
+            output.push_str("j __bf_bounds_error\n");
+        }
+        output.push('\n');
+
+        // Write the message to stderr, after any output that is still buffered, then exit
+        output.push_str("__bf_bounds_error:\n");
+        if self.buffered_io {
+            output.push_str("mv t4, a1\n");
+            output.push_str("mv t5, a2\n");
+            output.push_str("jal __bf_flush\n");
+            output.push_str("mv a1, t4\n");
+            output.push_str("mv a2, t5\n");
+        }
+        output.push_str("li a0, 2\n");
+        output.push_str(&format!("li a7, {}\n", SYS_WRITE));
+        output.push_str("ecall\n");
+        output.push_str("mv a0, a3\n");
+        output.push_str(&format!("li a7, {}\n", SYS_EXIT));
+        output.push_str("ecall\n\n");
+
+        output.push_str(".data\n");
+        for (index, error) in self.bounds_errors.iter().enumerate() {
+            output.push_str(&format!(
+                "bounds_message_{}: .ascii \"{}\"\n",
+                index,
+                error.message.escape_default()
+            ));
+        }
+        output.push('\n');
+    }
+
+    /// Generate the start of the library runtime's function, which saves the registers that it
+    /// uses and points s0 to the starting cell of the tape passed in a0
+    fn library_prologue(&self, output: &mut String) {
+        output.push_str(".text\n");
+        output.push_str(&format!(".globl {}\n", LIBRARY_FUNCTION));
+        output.push_str(&format!("{}:\n", LIBRARY_FUNCTION));
+        output.push_str(&format!("addi sp, sp, -{}\n", self.frame_size()));
+        for (index, register) in SAVED_REGISTERS.iter().enumerate() {
+            output.push_str(&format!(
+                "{} {}, {}(sp)\n",
+                self.store_register(),
+                register,
+                index as i64 * self.register_bytes()
+            ));
+        }
+        output.push_str("mv s7, a2\n");
+
+        // Bounds checking keeps the addresses of the first and last cells in s5 and s6
+        if self.bounds_check {
+            output.push_str("mv s5, a0\n");
+            output.push_str("addi t0, a1, -1\n");
+            let shift = self.dialect.cell_width.bytes().trailing_zeros();
+            if shift > 0 {
+                output.push_str(&format!("slli t0, t0, {}\n", shift));
+            }
+            output.push_str("add s6, a0, t0\n");
+        }
+
+        output.push_str("mv s0, a0\n");
+        if self.dialect.tape_start != 0 {
+            add_immediate(output, "s0", self.bytes(self.dialect.tape_start as isize));
+        }
+        output.push('\n');
+    }
+
+    /// Generate code for the instruction at `index` in the program
+    fn generate(&mut self, program: &[Instruction], index: usize, output: &mut String) {
+        if self.bounds_check && index == 0 {
+            self.check_region(program, 0, output);
+        }
+
+        // Moving the pointer and I/O use the current cell in memory
+        if matches!(
+            program[index],
+            Instruction::AddPtr { .. }
+                | Instruction::SubPtr { .. }
+                | Instruction::Scan { .. }
+                | Instruction::Read { .. }
+                | Instruction::Write { .. }
+        ) {
+            self.spill_cell(output);
+        }
+
+        match &program[index] {
+            Instruction::AddPtr { count } => {
+                add_immediate(output, "s0", self.bytes(*count as isize));
+                output.push('\n');
+                self.cell = CachedCell::default();
+            }
+            Instruction::SubPtr { count } => {
+                add_immediate(output, "s0", -self.bytes(*count as isize));
+                output.push('\n');
+                self.cell = CachedCell::default();
+            }
+            Instruction::AddByte { offset: 0, delta } if self.cache_cell => {
+                let delta = self.constant(*delta);
+                if delta != 0 {
+                    self.load_cell(output);
+                    add_immediate(output, "s1", delta);
+                    output.push('\n');
+                    self.cell.dirty = true;
+                    self.cell.overflowed = self.dialect.cell_width.bits() < self.arch.xlen.bits();
+                }
+            }
+            Instruction::AddByte { offset, delta } => {
+                // Cells wrap, so a delta above half the cell's range is the same as a negative delta
+                let delta = self.constant(*delta);
+                if delta != 0 {
+                    let scratch = self.scratch();
+                    let address = cell_address(output, self.bytes(*offset));
+                    output.push_str(&format!("{} {}, {}\n", self.load(), scratch, address));
+                    add_immediate(output, scratch, delta);
+                    output.push_str(&format!("{} {}, {}\n\n", self.store(), scratch, address));
+                }
+            }
+            Instruction::Read { count } => {
+                self.read(output, index, *count);
+                self.cell = CachedCell::default();
+            }
+            Instruction::Write { count } if self.buffered_io => {
+                output.push_str("lbu a0, (s0)\n");
+                output.push_str(&format!("li a1, {}\n", count));
+                output.push_str("jal __bf_write\n\n");
+            }
+            Instruction::Write { count } => match self.runtime {
+                Runtime::Library => {
+                    for _ in 0..*count {
+                        output.push_str("lbu a1, (s0)\n");
+                        self.callback(output, IO_WRITE);
+                    }
+                    output.push('\n');
+                }
+                Runtime::Rars => {
+                    output.push_str("lbu a0, (s0)\n");
+                    output.push_str(&format!("li a7, {}\n", RARS_PRINT_CHAR));
+
+                    for _ in 0..*count {
+                        output.push_str("ecall\n");
+                    }
+
+                    output.push('\n');
+                }
+                Runtime::Linux => {
+                    // Write directly from the current cell
+                    for _ in 0..*count {
+                        linux_syscall(output, SYS_WRITE, 1);
+                    }
+                    output.push('\n');
+                }
+            },
+            Instruction::LoopStart { end } => {
+                // Skip the loop if the current cell is zero. The loop is repeated by the test at
+                // its end, so the test here only runs once.
+                self.test_cell(output);
+                if self.far_loops.contains(&index) {
+                    output.push_str(&format!("bnez s1, start_{}\n", index));
+                    output.push_str(&format!("j end_{}\n", end));
+                } else {
+                    output.push_str(&format!("beqz s1, end_{}\n", end));
+                }
+                output.push_str(&format!("start_{}:\n\n", index));
+
+                // Both tests leave the current cell in s1 and memory
+                self.cell = CachedCell::CLEAN;
+            }
+            Instruction::LoopEnd { start } => {
+                self.test_cell(output);
+                if self.far_loops.contains(start) {
+                    output.push_str(&format!("beqz s1, end_{}\n", index));
+                    output.push_str(&format!("j start_{}\n", start));
+                } else {
+                    output.push_str(&format!("bnez s1, start_{}\n", start));
+                }
+                output.push_str(&format!("end_{}:\n\n", index));
+                self.cell = CachedCell::CLEAN;
+            }
+            Instruction::Scan { stride } => {
+                let words =
+                    *stride == 1 && self.is_rv64() && self.dialect.cell_width == CellWidth::Bits8;
+                if words && !self.bounds_check {
+                    scan_words(output, index);
+                } else {
+                    // Step back once so that the loop can step before testing each cell
+                    add_immediate(output, "s0", -self.bytes(*stride));
+                    output.push_str(&format!("scan_{}:\n", index));
+                    add_immediate(output, "s0", self.bytes(*stride));
+                    if self.bounds_check {
+                        let label = format!("scan_{}", index);
+                        self.check_range(output, &label, (0, index), (0, index));
+                    }
+                    output.push_str(&format!("{} s1, (s0)\n", self.load()));
+                    output.push_str(&format!("bnez s1, scan_{}\n\n", index));
+                }
+
+                // The scan stops once it loads a zero cell into s1
+                self.cell = CachedCell::CLEAN;
+            }
+            Instruction::SetByte { offset: 0, value } if self.cache_cell => {
+                output.push_str(&format!("li s1, {}\n\n", self.constant(*value)));
+                self.cell = CachedCell {
+                    dirty: true,
+                    ..CachedCell::CLEAN
+                };
+            }
+            Instruction::SetByte { offset, value } => {
+                let address = cell_address(output, self.bytes(*offset));
+                let value = self.constant(*value);
+                if value == 0 {
+                    output.push_str(&format!("{} zero, {}\n\n", self.store(), address));
+                } else {
+                    let scratch = self.scratch();
+                    output.push_str(&format!("li {}, {}\n", scratch, value));
+                    output.push_str(&format!("{} {}, {}\n\n", self.store(), scratch, address));
+                }
+            }
+            Instruction::MulAdd { offset, factor } => {
+                // Consecutive MulAdds share the value of the current cell in s1
+                if self.cache_cell {
+                    self.load_cell(output);
+                } else if index == 0 || !matches!(program[index - 1], Instruction::MulAdd { .. }) {
+                    output.push_str(&format!("{} s1, (s0)\n", self.load()));
+                }
+
+                let address = cell_address(output, self.bytes(*offset));
+                output.push_str(&format!("{} t0, {}\n", self.load(), address));
+                multiply_add(output, self.constant(*factor), self.arch.m);
+                output.push_str(&format!("{} t0, {}\n\n", self.store(), address));
+            }
+        }
+
+        // Moving the pointer, or entering or leaving a loop, starts a new region to check
+        if self.bounds_check
+            && matches!(
+                program[index],
+                Instruction::AddPtr { .. }
+                    | Instruction::SubPtr { .. }
+                    | Instruction::Scan { .. }
+                    | Instruction::LoopStart { .. }
+                    | Instruction::LoopEnd { .. }
+            )
+        {
+            self.check_region(program, index + 1, output);
+        }
+
+        // Without the cache, every instruction loads the current cell again
+        if !self.cache_cell {
+            self.cell = CachedCell::default();
+        }
+    }
+
+    /// Find the loops that are too large for a branch at one end to reach the other, which must
+    /// branch over a `j` instead. The code for each instruction is measured once, then loops are
+    /// marked as far until every loop left is in range, since the extra `j` instructions of a far
+    /// loop make the loops around it larger.
+    fn far_loops(&self, program: &[Instruction]) -> HashSet<usize> {
+        let mut generator = self.clone();
+        generator.far_loops.clear();
+        let mut sizes: Vec<u64> = (0..program.len())
+            .map(|index| {
+                let mut code = String::new();
+                generator.generate(program, index, &mut code);
+                assembler::code_size(&code, self.arch.xlen).expect("generated invalid code")
+            })
+            .collect();
+
+        let mut far = HashSet::new();
+        loop {
+            // The offset of the code for each instruction, followed by the end of the code
+            let mut offsets = vec![0];
+            for size in &sizes {
+                offsets.push(offsets[offsets.len() - 1] + size);
+            }
+
+            let mut changed = false;
+            for (index, inst) in program.iter().enumerate() {
+                if let Instruction::LoopStart { end } = *inst {
+                    if !far.contains(&index) && offsets[end + 1] - offsets[index] > BRANCH_RANGE {
+                        far.insert(index);
+                        sizes[index] += INSTRUCTION_LENGTH;
+                        sizes[end] += INSTRUCTION_LENGTH;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return far;
+            }
+        }
+    }
+
+    /// Generate code to load the current cell into s1, unless it is already cached there
+    fn load_cell(&mut self, output: &mut String) {
+        if !self.cell.valid {
+            output.push_str(&format!("{} s1, (s0)\n", self.load()));
+            self.cell = CachedCell::CLEAN;
+        }
+    }
+
+    /// Generate code to store the cached current cell, if it has changed since it was stored
+    fn spill_cell(&mut self, output: &mut String) {
+        if self.cell.dirty {
+            output.push_str(&format!("{} s1, (s0)\n", self.store()));
+            self.cell.dirty = false;
+        }
+    }
+
+    /// Generate code to put the value of the current cell in s1 for a loop test, storing it first
+    /// so that it is also in memory on both sides of the branch
+    fn test_cell(&mut self, output: &mut String) {
+        self.spill_cell(output);
+        if !self.cell.valid {
+            self.load_cell(output);
+        } else if self.cell.overflowed {
+            let width = self.dialect.cell_width.bits();
+            if width == 8 {
+                output.push_str("andi s1, s1, 255\n");
+            } else {
+                let shift = self.arch.xlen.bits() - width;
+                output.push_str(&format!("slli s1, s1, {}\n", shift));
+                output.push_str(&format!("srli s1, s1, {}\n", shift));
+            }
+            self.cell.overflowed = false;
+        }
+    }
+
+    /// The register that holds a cell other than the current one while it is changed
+    fn scratch(&self) -> &'static str {
+        if self.cache_cell {
+            "t2"
+        } else {
+            "s1"
+        }
+    }
+
+    /// Interprets a wrapping cell value as the signed constant that is cheapest to generate
+    fn constant(&self, value: u64) -> i64 {
+        let width = self.dialect.cell_width;
+        width.signed(width.wrap(value))
+    }
+}
+
+impl Backend for RiscV {
+    fn name(&self) -> &'static str {
+        if self.is_rv64() {
+            "riscv64"
+        } else {
+            "riscv32"
+        }
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        match name {
+            "arch" => {
+                let arch = Arch::from_name(value).ok_or_else(|| OptionError::InvalidValue {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })?;
+                // The register width is chosen by the target
+                if arch.xlen != self.arch.xlen {
+                    return Err(OptionError::Unsupported(format!(
+                        "the {} target cannot generate {} code",
+                        self.name(),
+                        value
+                    )));
+                }
+                self.arch = arch;
+            }
+            "buffered-io" => self.buffered_io = parse_bool(name, value)?,
+            "bounds-check" => self.bounds_check = parse_bool(name, value)?,
+            "cache-cell" => self.cache_cell = parse_bool(name, value)?,
+            "compressed" => self.compressed = parse_bool(name, value)?,
+            "runtime" => {
+                self.runtime = match value {
+                    "rars" => Runtime::Rars,
+                    "linux" => Runtime::Linux,
+                    "library" => Runtime::Library,
+                    _ => {
+                        return Err(OptionError::InvalidValue {
+                            name: name.to_string(),
+                            value: value.to_string(),
+                        })
+                    }
+                }
+            }
+            _ => return Err(OptionError::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), OptionError> {
+        if self.dialect.cell_width == CellWidth::Bits64 && !self.is_rv64() {
+            return Err(OptionError::Unsupported(
+                "64-bit cells require an RV64 target".to_string(),
+            ));
+        }
+        if self.compressed && !self.arch.c {
+            return Err(OptionError::Unsupported(format!(
+                "compressed instructions require the C extension, which {} does not include",
+                self.arch.name()
+            )));
+        }
+        if self.compressed && self.runtime == Runtime::Rars {
+            return Err(OptionError::Unsupported(
+                "RARS does not support compressed instructions".to_string(),
+            ));
+        }
+        if self.runtime == Runtime::Library && self.buffered_io {
+            return Err(OptionError::Unsupported(
+                "the library runtime does not support buffered I/O".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn prologue(&mut self, output: &mut String) {
+        // The backend may be reused for another program
+        self.bounds_errors.clear();
+        self.cell = CachedCell::default();
+
+        // Record the ISA, which assemblers also use to reject any instruction outside of it, and
+        // whether to compress instructions, which they do by default when the ISA includes the C
+        // extension. RARS does not accept these directives.
+        if self.runtime != Runtime::Rars {
+            output.push_str(&format!(".attribute arch, \"{}\"\n", self.arch.name()));
+            if self.arch.c {
+                output.push_str(if self.compressed {
+                    ".option rvc\n"
+                } else {
+                    ".option norvc\n"
+                });
+            }
+            output.push('\n');
+        }
+
+        if self.runtime == Runtime::Library {
+            self.library_prologue(output);
+            return;
+        }
+
+        // Generate code to allocate the memory space. On Linux, it is zeroed by the loader.
+        match self.runtime {
+            Runtime::Rars => output.push_str(".data\n"),
+            _ => output.push_str(".bss\n"),
+        }
+
+        // Cells must be aligned to their width, and RV64 scans load whole words from memory
+        let alignment = if self.is_rv64() {
+            8
+        } else {
+            self.dialect.cell_width.bytes()
+        };
+        if alignment > 1 {
+            output.push_str(&format!(".align {}\n", alignment.trailing_zeros()));
+        }
+        output.push_str(&format!(
+            "memory: .space {}\n",
+            self.dialect.tape_length * self.dialect.cell_width.bytes()
+        ));
+        if self.buffered_io {
+            output.push_str(&format!("output_buffer: .space {}\n", OUTPUT_BUFFER_SIZE));
+            output.push_str(&format!("input_buffer: .space {}\n", INPUT_BUFFER_SIZE));
+        }
+        output.push('\n');
+
+        // Register s0 will be our pointer. Set it to point to the starting cell
+        output.push_str(".text\n");
+        match self.runtime {
+            Runtime::Rars => output.push_str("main:\n"),
+            _ => {
+                output.push_str(".globl _start\n");
+                output.push_str("_start:\n");
+            }
+        }
+        output.push_str("la s0, memory\n");
+        if self.dialect.tape_start != 0 {
+            add_immediate(output, "s0", self.bytes(self.dialect.tape_start as isize));
+        }
+
+        // Buffered I/O keeps the number of bytes waiting to be written in s2,
+        // and the addresses of the next and last bytes read but not yet used in s3 and s4
+        if self.buffered_io {
+            output.push_str("li s2, 0\n");
+            output.push_str("li s3, 0\n");
+            output.push_str("li s4, 0\n");
+        }
+
+        // Bounds checking keeps the addresses of the first and last cells in s5 and s6
+        if self.bounds_check {
+            output.push_str("la s5, memory\n");
+            let last_cell = self.bytes(self.dialect.tape_length as isize - 1);
+            add_immediate_into(output, "s6", "s5", last_cell);
+        }
+        output.push('\n');
+    }
+
+    fn instruction(&mut self, program: &[Instruction], index: usize, output: &mut String) {
+        // Choose how to branch for every loop before generating any code
+        if index == 0 {
+            self.far_loops = self.far_loops(program);
+        }
+        self.generate(program, index, output);
+    }
+
+    fn epilogue(&mut self, output: &mut String) {
+        // The library runtime's caller can read the tape afterwards
+        self.spill_cell(output);
+
+        if self.runtime == Runtime::Library {
+            // Generate code to return 0, restoring the saved registers
+            output.push_str("li a0, 0\n");
+            output.push_str("__bf_return:\n");
+            for (index, register) in SAVED_REGISTERS.iter().enumerate() {
+                output.push_str(&format!(
+                    "{} {}, {}(sp)\n",
+                    self.load_register(),
+                    register,
+                    index as i64 * self.register_bytes()
+                ));
+            }
+            output.push_str(&format!("addi sp, sp, {}\n", self.frame_size()));
+            output.push_str("ret\n\n");
+
+            if self.bounds_check {
+                self.bounds_error_runtime(output);
+            }
+            return;
+        }
+
+        // Generate code to exit, writing any buffered output first
+        if self.buffered_io {
+            output.push_str("jal __bf_flush\n");
+        }
+        output.push_str("li a0, 0\n");
+        output.push_str(&format!("li a7, {}\n", SYS_EXIT));
+        output.push_str("ecall\n\n");
+
+        if self.buffered_io {
+            buffered_io_runtime(output);
+        }
+        if self.bounds_check {
+            self.bounds_error_runtime(output);
+        }
+    }
+}
+
+/// Generate the subroutines used for buffered I/O. RARS supports the Linux `read` and `write`
+/// system call numbers, so the same code works for both runtimes.
+///
+/// - `__bf_write` appends `a1` copies of the byte in `a0` to the output buffer, writing the
+///   buffer out after each newline and whenever it is full.
+/// - `__bf_read` returns the next byte of input in `a0`, or -1 at the end of input. When the
+///   input buffer is empty, it writes any buffered output and then refills it.
+/// - `__bf_flush` writes out the output buffer.
+fn buffered_io_runtime(output: &mut String) {
+    output.push_str("__bf_write:\n");
+    output.push_str("la t2, output_buffer\n");
+    output.push_str("__bf_write_loop:\n");
+    output.push_str("add t3, t2, s2\n");
+    output.push_str("sb a0, 0(t3)\n");
+    output.push_str("addi s2, s2, 1\n");
+    output.push_str("addi a1, a1, -1\n");
+    output.push_str("li t3, 10\n");
+    output.push_str("beq a0, t3, __bf_write_flush\n");
+    output.push_str(&format!("li t3, {}\n", OUTPUT_BUFFER_SIZE));
+    output.push_str("beq s2, t3, __bf_write_flush\n");
+    output.push_str("__bf_write_next:\n");
+    output.push_str("bnez a1, __bf_write_loop\n");
+    output.push_str("ret\n");
+    output.push_str("__bf_write_flush:\n");
+    output.push_str("mv t4, a0\n");
+    output.push_str("mv t5, a1\n");
+    output.push_str("mv t6, ra\n");
+    output.push_str("jal __bf_flush\n");
+    output.push_str("mv a0, t4\n");
+    output.push_str("mv a1, t5\n");
+    output.push_str("mv ra, t6\n");
+    output.push_str("la t2, output_buffer\n");
+    output.push_str("j __bf_write_next\n\n");
+
+    output.push_str("__bf_read:\n");
+    output.push_str("bne s3, s4, __bf_read_byte\n");
+    output.push_str("mv t6, ra\n");
+    output.push_str("jal __bf_flush\n");
+    output.push_str("mv ra, t6\n");
+    output.push_str("li a0, 0\n");
+    output.push_str("la a1, input_buffer\n");
+    output.push_str(&format!("li a2, {}\n", INPUT_BUFFER_SIZE));
+    output.push_str(&format!("li a7, {}\n", SYS_READ));
+    output.push_str("ecall\n");
+    output.push_str("blez a0, __bf_read_end\n");
+    output.push_str("la s3, input_buffer\n");
+    output.push_str("add s4, s3, a0\n");
+    output.push_str("__bf_read_byte:\n");
+    output.push_str("lbu a0, 0(s3)\n");
+    output.push_str("addi s3, s3, 1\n");
+    output.push_str("ret\n");
+    output.push_str("__bf_read_end:\n");
+    output.push_str("li a0, -1\n");
+    output.push_str("ret\n\n");
+
+    // Writes may be partial, so keep writing until the whole buffer is written or an error occurs
+    output.push_str("__bf_flush:\n");
+    output.push_str("la a1, output_buffer\n");
+    output.push_str("mv a2, s2\n");
+    output.push_str("__bf_flush_loop:\n");
+    output.push_str("beqz a2, __bf_flush_end\n");
+    output.push_str("li a0, 1\n");
+    output.push_str(&format!("li a7, {}\n", SYS_WRITE));
+    output.push_str("ecall\n");
+    output.push_str("blez a0, __bf_flush_end\n");
+    output.push_str("add a1, a1, a0\n");
+    output.push_str("sub a2, a2, a0\n");
+    output.push_str("j __bf_flush_loop\n");
+    output.push_str("__bf_flush_end:\n");
+    output.push_str("li s2, 0\n");
+    output.push_str("ret\n\n");
+}
+
+/// Generate code to make a Linux `read` or `write` system call that transfers the current cell
+/// to or from the file descriptor `fd`
+fn linux_syscall(output: &mut String, number: u32, fd: u32) {
+    output.push_str(&format!("li a0, {}\n", fd));
+    output.push_str("mv a1, s0\n");
+    output.push_str("li a2, 1\n");
+    output.push_str(&format!("li a7, {}\n", number));
+    output.push_str("ecall\n");
+}
+
+/// Generate code to add the constant `value` to `register`.
+/// Constants that do not fit in an `addi` immediate are first loaded into scratch register t0.
+fn add_immediate(output: &mut String, register: &str, value: i64) {
+    add_immediate_into(output, register, register, value);
+}
+
+/// Generate code to set `destination` to `source` plus the constant `value`.
+/// Constants that do not fit in an `addi` immediate are first loaded into scratch register t0.
+fn add_immediate_into(output: &mut String, destination: &str, source: &str, value: i64) {
+    if (IMMEDIATE_MIN..=IMMEDIATE_MAX).contains(&value) {
+        output.push_str(&format!("addi {}, {}, {}\n", destination, source, value));
+    } else {
+        output.push_str(&format!("li t0, {}\n", value));
+        output.push_str(&format!("add {}, {}, t0\n", destination, source));
+    }
+}
+
+/// Generate code to compute the address `offset` bytes away from the pointer.
+/// Returns the address operand to use in a load or store, which may refer to scratch register t1.
+fn cell_address(output: &mut String, offset: i64) -> String {
+    if (IMMEDIATE_MIN..=IMMEDIATE_MAX).contains(&offset) {
+        format!("{}(s0)", offset)
+    } else {
+        output.push_str(&format!("li t1, {}\n", offset));
+        output.push_str("add t1, s0, t1\n");
+        "(t1)".to_string()
+    }
+}
+
+/// Generate code to add s1 multiplied by the constant `factor` to t0, using t2 as scratch.
+/// Without the M extension, the product is built from shifts and additions.
+fn multiply_add(output: &mut String, factor: i64, m_extension: bool) {
+    let op = if factor < 0 { "sub" } else { "add" };
+    let magnitude = factor.unsigned_abs();
+
+    if magnitude == 1 {
+        output.push_str(&format!("{} t0, t0, s1\n", op));
+    } else if m_extension {
+        output.push_str(&format!("li t2, {}\n", factor));
+        output.push_str("mul t2, s1, t2\n");
+        output.push_str("add t0, t0, t2\n");
+    } else {
+        for bit in (0..64).filter(|bit| magnitude & (1 << bit) != 0) {
+            if bit == 0 {
+                output.push_str(&format!("{} t0, t0, s1\n", op));
+            } else {
+                output.push_str(&format!("slli t2, s1, {}\n", bit));
+                output.push_str(&format!("{} t0, t0, t2\n", op));
+            }
+        }
+    }
+}
+
+/// Generate code to move the pointer forward to the nearest zero cell, using the labels
+/// `scan_{index}`, `scan_word_{index}`, and `scan_end_{index}`. Once the pointer is word
+/// aligned, whole 64-bit words are tested for a zero byte at a time.
+fn scan_words(output: &mut String, index: usize) {
+    // Test single cells until the pointer is aligned
+    output.push_str(&format!("scan_{}:\n", index));
+    output.push_str("lbu s1, (s0)\n");
+    output.push_str(&format!("beqz s1, scan_end_{}\n", index));
+    output.push_str("addi s0, s0, 1\n");
+    output.push_str("andi t0, s0, 7\n");
+    output.push_str(&format!("bnez t0, scan_{}\n", index));
+
+    // A word w contains a zero byte iff (w - 0x0101..01) & ~w & 0x8080..80 is nonzero.
+    // Once a word with a zero byte is found, go back to finding it one cell at a time.
+    output.push_str("li t1, 0x0101010101010101\n");
+    output.push_str("slli t2, t1, 7\n");
+    output.push_str(&format!("scan_word_{}:\n", index));
+    output.push_str("ld t0, (s0)\n");
+    output.push_str("sub t3, t0, t1\n");
+    output.push_str("not t4, t0\n");
+    output.push_str("and t3, t3, t4\n");
+    output.push_str("and t3, t3, t2\n");
+    output.push_str(&format!("bnez t3, scan_{}\n", index));
+    output.push_str("addi s0, s0, 8\n");
+    output.push_str(&format!("j scan_word_{}\n", index));
+    output.push_str(&format!("scan_end_{}:\n\n", index));
+}
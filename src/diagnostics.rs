@@ -0,0 +1,304 @@
+use std::fmt::Write;
+
+use crate::parser::{Bracket, ParseError, Span};
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A message attached to a location in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    /// Primary labels mark the cause of the diagnostic, secondary labels add context
+    pub primary: bool,
+}
+
+/// An error or warning about a Brainfuck source string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    /// Extra information that does not point at the source, shown after the labels
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Constructs an error with no labels
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Constructs a warning with no labels
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Adds a label marking the cause of this diagnostic
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    /// Adds a label giving context for this diagnostic
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    /// Adds a note that is not attached to the source
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+/// Converts a parse error into one diagnostic per unmatched bracket
+pub fn from_parse_error(error: &ParseError, source: &str) -> Vec<Diagnostic> {
+    error
+        .unmatched
+        .iter()
+        .map(|unmatched| match unmatched.bracket {
+            Bracket::Open => Diagnostic::error("unmatched `[`")
+                .with_primary(unmatched.span, "this loop is never closed")
+                .with_secondary(
+                    span_at(source, source.trim_end().len()),
+                    "expected a matching `]` before the end of the file",
+                ),
+            Bracket::Close => {
+                let diagnostic = Diagnostic::error("unmatched `]`")
+                    .with_primary(unmatched.span, "this `]` has no matching `[`");
+
+                // Every `[` before an unmatched `]` has already been closed, so point at the nearest one
+                match source[..unmatched.span.offset].rfind('[') {
+                    Some(offset) => diagnostic.with_secondary(
+                        span_at(source, offset),
+                        "the nearest `[` is already closed before this point",
+                    ),
+                    None => diagnostic.with_note("no `[` opens a loop before it"),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Finds likely mistakes in a source string that do not prevent it from compiling
+pub fn lint(source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    // The previous command in the source, ignoring comments
+    let mut previous: Option<(char, usize)> = None;
+
+    for (offset, command) in source.char_indices() {
+        if !"<>+-,.[]".contains(command) {
+            continue;
+        }
+
+        if let (Some(('[', start)), ']') = (previous, command) {
+            diagnostics.push(
+                Diagnostic::warning("empty loop")
+                    .with_primary(
+                        span_at(source, start),
+                        "this loop never terminates if the current cell is nonzero",
+                    )
+                    .with_secondary(span_at(source, offset), "the loop ends here"),
+            );
+        }
+
+        previous = Some((command, offset));
+    }
+
+    diagnostics
+}
+
+/// Computes the span of the character at the given byte offset into the source
+pub fn span_at(source: &str, offset: usize) -> Span {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    Span {
+        offset,
+        len: source[offset..].chars().next().map_or(0, char::len_utf8),
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+/// ANSI escape codes used when rendering in color
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics in the style of rustc
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Renderer {
+    /// Whether to decorate the output with ANSI color codes
+    pub color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Renderer { color }
+    }
+
+    /// Renders the diagnostic, quoting the lines of `source` that its labels point at
+    pub fn render(&self, diagnostic: &Diagnostic, filename: &str, source: &str) -> String {
+        let mut output = String::new();
+
+        let (name, severity_color) = match diagnostic.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+
+        // Header, e.g. `error: unmatched `[``
+        writeln!(
+            output,
+            "{}: {}",
+            self.paint(severity_color, name),
+            self.paint(BOLD, &diagnostic.message)
+        )
+        .unwrap();
+
+        let mut labels: Vec<&Label> = diagnostic.labels.iter().collect();
+        labels.sort_by_key(|label| (label.span.line, label.span.column));
+
+        // The gutter must be wide enough for the largest line number
+        let gutter = labels
+            .last()
+            .map_or(0, |label| label.span.line.to_string().len());
+        let pipe = self.paint(BLUE, "|");
+        if labels.is_empty() {
+            self.render_notes(&mut output, diagnostic, gutter);
+            return output;
+        }
+
+        // Location of the primary label, e.g. ` --> prog.bf:3:14`
+        let location = diagnostic
+            .labels
+            .iter()
+            .find(|label| label.primary)
+            .unwrap_or(labels[0])
+            .span;
+        writeln!(
+            output,
+            "{:gutter$}{} {}:{}:{}",
+            "",
+            self.paint(BLUE, "-->"),
+            filename,
+            location.line,
+            location.column,
+            gutter = gutter
+        )
+        .unwrap();
+        writeln!(output, "{:gutter$} {}", "", pipe, gutter = gutter).unwrap();
+
+        let lines: Vec<&str> = source.lines().collect();
+        let mut previous_line: Option<usize> = None;
+
+        for label in labels {
+            let line = label.span.line;
+
+            // Quote each source line only once, and mark skipped lines
+            if previous_line != Some(line) {
+                if previous_line.is_some_and(|previous| line > previous + 1) {
+                    writeln!(output, "{}", self.paint(BLUE, "...")).unwrap();
+                }
+
+                let text = lines.get(line - 1).copied().unwrap_or("");
+                writeln!(
+                    output,
+                    "{} {} {}",
+                    self.paint(BLUE, &format!("{:>gutter$}", line, gutter = gutter)),
+                    pipe,
+                    text.trim_end()
+                )
+                .unwrap();
+                previous_line = Some(line);
+            }
+
+            let (marker, color) = if label.primary {
+                ('^', severity_color)
+            } else {
+                ('-', BLUE)
+            };
+            let characters = source
+                .get(label.span.offset..label.span.offset + label.span.len)
+                .map_or(0, |text| text.chars().count());
+            let underline: String = std::iter::repeat_n(marker, characters.max(1)).collect();
+
+            // Copy any tabs before the label from the quoted line, so that the marker lines up
+            // however wide the tabs are displayed
+            let text = lines.get(line - 1).copied().unwrap_or("");
+            let mut indent: String = text
+                .chars()
+                .take(label.span.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let padding = label.span.column - 1 - indent.chars().count();
+            indent.extend(std::iter::repeat_n(' ', padding));
+
+            writeln!(
+                output,
+                "{:gutter$} {} {}{}",
+                "",
+                pipe,
+                indent,
+                self.paint(color, &format!("{} {}", underline, label.message)),
+                gutter = gutter
+            )
+            .unwrap();
+        }
+
+        self.render_notes(&mut output, diagnostic, gutter);
+        output
+    }
+
+    /// Renders the notes of a diagnostic, e.g. `  = note: ...`
+    fn render_notes(&self, output: &mut String, diagnostic: &Diagnostic, gutter: usize) {
+        for note in &diagnostic.notes {
+            writeln!(
+                output,
+                "{:gutter$} {} {}",
+                "",
+                self.paint(BLUE, "="),
+                format_args!("{}: {}", self.paint(BOLD, "note"), note),
+                gutter = gutter
+            )
+            .unwrap();
+        }
+    }
+
+    /// Wraps the text in the given ANSI color code, if color is enabled
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// The width of each cell in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CellWidth {
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+}
+
+impl CellWidth {
+    /// Constructs a CellWidth from a number of bits, if it is a supported width
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(CellWidth::Bits8),
+            16 => Some(CellWidth::Bits16),
+            32 => Some(CellWidth::Bits32),
+            64 => Some(CellWidth::Bits64),
+            _ => None,
+        }
+    }
+
+    /// The number of bits in a cell
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::Bits8 => 8,
+            CellWidth::Bits16 => 16,
+            CellWidth::Bits32 => 32,
+            CellWidth::Bits64 => 64,
+        }
+    }
+
+    /// The number of bytes in a cell
+    pub fn bytes(self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    /// Reduces a value to one that fits in a cell, wrapping on overflow
+    pub fn wrap(self, value: u64) -> u64 {
+        match self {
+            CellWidth::Bits64 => value,
+            _ => value & ((1 << self.bits()) - 1),
+        }
+    }
+
+    /// Interprets a value as a signed number of this width, so that small negative values
+    /// such as a decrement by one can be represented as small negative constants
+    pub fn signed(self, value: u64) -> i64 {
+        let shift = 64 - self.bits();
+        ((value << shift) as i64) >> shift
+    }
+}
+
+impl fmt::Display for CellWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
+
+/// What the `,` instruction does to the current cell at the end of input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofBehavior {
+    /// Leave the cell unchanged
+    Unchanged,
+    /// Set the cell to 0
+    Zero,
+    /// Set the cell to -1, which is 255 for 8-bit cells
+    MinusOne,
+}
+
+impl EofBehavior {
+    /// Constructs an EofBehavior from its name, as used on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unchanged" => Some(EofBehavior::Unchanged),
+            "zero" => Some(EofBehavior::Zero),
+            "minus-one" => Some(EofBehavior::MinusOne),
+            _ => None,
+        }
+    }
+}
+
+/// The properties of the machine that a Brainfuck program runs on, which differ between
+/// Brainfuck implementations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    /// The number of cells in memory
+    pub tape_length: usize,
+    /// The width of each cell
+    pub cell_width: CellWidth,
+    /// The index of the cell that the pointer starts at, for programs that move left first
+    pub tape_start: usize,
+    /// What reading does at the end of input
+    pub eof: EofBehavior,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            tape_length: 30000,
+            cell_width: CellWidth::Bits8,
+            tape_start: 0,
+            eof: EofBehavior::Unchanged,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    compiler::{self, Backend},
+    dialect::Dialect,
+    instruction::Instruction,
+    interpreter::Interpreter,
+    optimizer::PassManager,
+    parser::parse,
+    riscv_sim::{self, Machine, Xlen},
+};
+
+/// The default number of instructions that a program may run for in each runner
+pub const DEFAULT_STEP_LIMIT: u64 = 100_000_000;
+
+/// A Brainfuck program and the input to run it with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub source: String,
+    pub input: Vec<u8>,
+}
+
+/// Load every `.bf` file in a directory as a Case, in order of name.
+/// The input for `name.bf` is read from `name.in`, if it exists.
+pub fn load_corpus(directory: &Path) -> io::Result<Vec<Case>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "bf"));
+    paths.sort();
+
+    let mut cases = Vec::with_capacity(paths.len());
+    for path in paths {
+        let input_path = path.with_extension("in");
+        let input = if input_path.exists() {
+            fs::read(input_path)?
+        } else {
+            Vec::new()
+        };
+
+        cases.push(Case {
+            name: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            source: fs::read_to_string(&path)?,
+            input,
+        });
+    }
+    Ok(cases)
+}
+
+/// The ways of running a program whose outputs are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runner {
+    /// The interpreter running the parsed program, whose output is the expected output
+    Unoptimized,
+    /// The interpreter running the optimized program
+    Optimized,
+    /// The emulator running the assembly generated for the optimized program
+    Emulated,
+}
+
+impl fmt::Display for Runner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Runner::Unoptimized => write!(f, "unoptimized interpreter"),
+            Runner::Optimized => write!(f, "optimized interpreter"),
+            Runner::Emulated => write!(f, "emulated assembly"),
+        }
+    }
+}
+
+/// The reason that a case failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The name of the case
+    pub case: String,
+    /// The runner that failed or disagreed with the unoptimized interpreter
+    pub runner: Runner,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.case, self.runner, self.message)
+    }
+}
+
+impl Error for Failure {}
+
+/// Runs cases with the interpreter before and after optimization and with the emulator, and
+/// checks that their outputs are identical
+pub struct Harness {
+    /// The dialect that every runner uses
+    pub dialect: Dialect,
+    /// The passes used to optimize each program
+    pub passes: PassManager,
+    /// The backend that generates the assembly to emulate, which must be a RISC-V backend
+    pub backend: Box<dyn Backend>,
+    /// The number of instructions that a program may run for in each runner
+    pub step_limit: u64,
+}
+
+impl Harness {
+    /// Constructs a Harness with the default step limit
+    pub fn new(dialect: Dialect, passes: PassManager, backend: Box<dyn Backend>) -> Self {
+        Harness {
+            dialect,
+            passes,
+            backend,
+            step_limit: DEFAULT_STEP_LIMIT,
+        }
+    }
+
+    /// Run a case with every runner, returning the first failure or difference in output
+    pub fn check(&mut self, case: &Case) -> Result<(), Failure> {
+        let fail = |runner: Runner, message: String| Failure {
+            case: case.name.clone(),
+            runner,
+            message,
+        };
+
+        let program = parse(&case.source)
+            .map_err(|error| fail(Runner::Unoptimized, format!("failed to parse: {}", error)))?;
+        let expected = self
+            .interpret(&program, &case.input)
+            .map_err(|message| fail(Runner::Unoptimized, message))?;
+
+        let optimized = self.passes.run(&program);
+        let output = self
+            .interpret(&optimized, &case.input)
+            .map_err(|message| fail(Runner::Optimized, message))?;
+        compare(&expected, &output).map_err(|message| fail(Runner::Optimized, message))?;
+
+        let output = self
+            .emulate(&optimized, &case.input)
+            .map_err(|message| fail(Runner::Emulated, message))?;
+        compare(&expected, &output).map_err(|message| fail(Runner::Emulated, message))?;
+
+        Ok(())
+    }
+
+    /// Run a program in the interpreter, returning its output
+    fn interpret(&self, program: &[Instruction], input: &[u8]) -> Result<Vec<u8>, String> {
+        let mut interpreter = Interpreter::new(program, self.dialect);
+        interpreter.step_limit = Some(self.step_limit);
+
+        let mut output = Vec::new();
+        interpreter
+            .run(&mut &input[..], &mut output)
+            .map_err(|error| error.to_string())?;
+        Ok(output)
+    }
+
+    /// Compile a program and run it in the emulator, returning its output
+    fn emulate(&mut self, program: &[Instruction], input: &[u8]) -> Result<Vec<u8>, String> {
+        let xlen = Xlen::for_target(self.backend.name())
+            .ok_or_else(|| format!("cannot emulate target `{}`", self.backend.name()))?;
+        let assembly = compiler::compile(self.backend.as_mut(), program);
+        let program = riscv_sim::assemble(&assembly, xlen).map_err(|error| error.to_string())?;
+
+        let mut machine = Machine::new(&program);
+        machine.step_limit = Some(self.step_limit);
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let code = machine
+            .run(&mut &input[..], &mut stdout, &mut stderr)
+            .map_err(|error| error.to_string())?;
+        if code != 0 {
+            return Err(format!(
+                "exited with code {}: {}",
+                code,
+                String::from_utf8_lossy(&stderr).trim_end()
+            ));
+        }
+        Ok(stdout)
+    }
+}
+
+/// Compare a runner's output with the expected output, describing the first difference
+fn compare(expected: &[u8], output: &[u8]) -> Result<(), String> {
+    if expected == output {
+        return Ok(());
+    }
+
+    let index = expected
+        .iter()
+        .zip(output)
+        .position(|(expected, output)| expected != output)
+        .unwrap_or_else(|| expected.len().min(output.len()));
+    let byte = |bytes: &[u8]| match bytes.get(index) {
+        Some(byte) => format!("{:#04x}", byte),
+        None => "the end of output".to_string(),
+    };
+    Err(format!(
+        "output differs at byte {}: expected {}, found {} ({} bytes expected, {} found)",
+        index,
+        byte(expected),
+        byte(output),
+        expected.len(),
+        output.len()
+    ))
+}
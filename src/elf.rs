@@ -0,0 +1,618 @@
+use crate::{
+    isa::{Arch, Xlen},
+    riscv_sim::{assembler::TEXT_BASE, Program},
+};
+
+/// The size of a page. Loadable segments start on a page boundary, both in the file and in memory.
+const PAGE_SIZE: u64 = 0x1000;
+
+/// The size in bytes of an ELF64 program header. Only ELF64 executables are written.
+const PROGRAM_HEADER_SIZE: u64 = 56;
+
+/// The machine number of RISC-V
+const EM_RISCV: u16 = 243;
+
+/// The header flag marking code that uses compressed instructions
+const EF_RISCV_RVC: u32 = 1;
+
+/// Object file types
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+
+/// Segment types and permissions
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// Section types
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+
+/// Section flags
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+
+/// Symbol bindings and types
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// The calling conventions for floating-point arguments, which are recorded in the header of
+/// an object file so that the linker only combines objects that agree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatAbi {
+    /// Floating-point arguments are passed in integer registers
+    Soft,
+    /// Single-precision arguments are passed in floating-point registers, as in ilp32f and lp64f
+    Single,
+    /// Double-precision arguments are passed in floating-point registers, as in ilp32d and lp64d
+    Double,
+}
+
+impl FloatAbi {
+    /// Looks up a float ABI by its name on the command line
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "soft" => Some(FloatAbi::Soft),
+            "single" => Some(FloatAbi::Single),
+            "double" => Some(FloatAbi::Double),
+            _ => None,
+        }
+    }
+
+    /// The float ABI of the standard calling convention for the ISA, which passes arguments in
+    /// the widest floating-point registers that it has: lp64d for RV64GC, and ilp32 for RV32IM
+    pub fn default_for(arch: Arch) -> Self {
+        if arch.d {
+            FloatAbi::Double
+        } else if arch.f {
+            FloatAbi::Single
+        } else {
+            FloatAbi::Soft
+        }
+    }
+
+    /// The bits of the header flags that record the float ABI
+    fn flags(self) -> u32 {
+        match self {
+            FloatAbi::Soft => 0,
+            FloatAbi::Single => 2,
+            FloatAbi::Double => 4,
+        }
+    }
+}
+
+/// The sizes in bytes of the ELF header, section headers, and symbols of each file class
+fn header_size(xlen: Xlen) -> u64 {
+    match xlen {
+        Xlen::Rv32 => 52,
+        Xlen::Rv64 => 64,
+    }
+}
+
+fn section_header_size(xlen: Xlen) -> u64 {
+    match xlen {
+        Xlen::Rv32 => 40,
+        Xlen::Rv64 => 64,
+    }
+}
+
+fn symbol_size(xlen: Xlen) -> u64 {
+    match xlen {
+        Xlen::Rv32 => 16,
+        Xlen::Rv64 => 24,
+    }
+}
+
+/// Appends little-endian values to a file. ELF32 files are written for RV32 programs and
+/// ELF64 files for RV64 programs.
+#[derive(Debug)]
+struct Writer {
+    xlen: Xlen,
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn new(xlen: Xlen) -> Self {
+        Writer {
+            xlen,
+            bytes: Vec::new(),
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write an address, offset, or size, which is 4 bytes in ELF32 and 8 bytes in ELF64
+    fn word(&mut self, value: u64) {
+        match self.xlen {
+            Xlen::Rv32 => self.u32(value as u32),
+            Xlen::Rv64 => self.u64(value),
+        }
+    }
+
+    /// Overwrite an address, offset, or size that was written earlier
+    fn patch_word(&mut self, offset: usize, value: u64) {
+        match self.xlen {
+            Xlen::Rv32 => {
+                self.bytes[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes())
+            }
+            Xlen::Rv64 => self.bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    /// Write `size` zero bytes
+    fn zeros(&mut self, size: u64) {
+        self.bytes.resize(self.bytes.len() + size as usize, 0);
+    }
+
+    /// The offset of the next byte
+    fn offset(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// Pad with zeros to a multiple of `alignment`
+    fn align(&mut self, alignment: u64) {
+        while !self.offset().is_multiple_of(alignment) {
+            self.bytes.push(0);
+        }
+    }
+
+    /// Pad with zeros to `offset`, which must not be before the next byte
+    fn pad_to(&mut self, offset: u64) {
+        self.bytes.resize(offset as usize, 0);
+    }
+}
+
+/// A table of null-terminated strings, referred to by their offsets
+#[derive(Debug)]
+struct StringTable {
+    bytes: Vec<u8>,
+}
+
+impl StringTable {
+    /// Constructs a table holding only the empty string, at offset 0
+    fn new() -> Self {
+        StringTable { bytes: vec![0] }
+    }
+
+    /// Add a string, returning its offset
+    fn add(&mut self, string: &str) -> u32 {
+        if string.is_empty() {
+            return 0;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+/// The header of a section, which describes where its contents are in the file and in memory
+#[derive(Debug, Clone, Default)]
+struct SectionHeader {
+    name: &'static str,
+    kind: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    /// The index of a related section, such as the string table of a symbol table
+    link: u32,
+    /// Extra information, such as the index of the first global symbol in a symbol table
+    info: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+
+/// A label in the symbol table
+#[derive(Debug, Clone)]
+struct Symbol {
+    name: String,
+    value: u64,
+    /// The index of the section that the symbol is in
+    section: u16,
+    global: bool,
+    /// Whether the symbol is the start of a function that can be called from other files
+    function: bool,
+    /// The size in bytes of the function, or 0 for other symbols
+    size: u64,
+}
+
+/// Write a symbol table, with the local symbols first as ELF requires, and its string table.
+/// Returns the index of the first global symbol.
+fn write_symbols(output: &mut Writer, symbols: &[Symbol], names: &mut StringTable) -> u32 {
+    // The first symbol is always the null symbol
+    output.zeros(symbol_size(output.xlen));
+
+    let locals = symbols.iter().filter(|symbol| !symbol.global);
+    let globals = symbols.iter().filter(|symbol| symbol.global);
+    for symbol in locals.chain(globals) {
+        let binding = if symbol.global { STB_GLOBAL } else { STB_LOCAL };
+        let kind = if symbol.function { STT_FUNC } else { 0 };
+        let name = names.add(&symbol.name);
+
+        // The fields are in a different order in ELF32 and ELF64
+        match output.xlen {
+            Xlen::Rv32 => {
+                output.u32(name);
+                output.u32(symbol.value as u32);
+                output.u32(symbol.size as u32);
+                output.u8(binding << 4 | kind);
+                output.u8(0);
+                output.u16(symbol.section);
+            }
+            Xlen::Rv64 => {
+                output.u32(name);
+                output.u8(binding << 4 | kind);
+                output.u8(0);
+                output.u16(symbol.section);
+                output.u64(symbol.value);
+                output.u64(symbol.size);
+            }
+        }
+    }
+
+    1 + symbols.iter().filter(|symbol| !symbol.global).count() as u32
+}
+
+/// Write the ELF header. The program and section headers follow the contents of the file, so
+/// their offsets are filled in by `finish`.
+fn write_header(output: &mut Writer, kind: u16, entry: u64, program_headers: u16, flags: u32) {
+    let xlen = output.xlen;
+    output.bytes.extend_from_slice(b"\x7fELF");
+    // 32 or 64-bit, little-endian, version 1, System V ABI
+    let class = match xlen {
+        Xlen::Rv32 => 1,
+        Xlen::Rv64 => 2,
+    };
+    output.bytes.extend_from_slice(&[class, 1, 1, 0]);
+    output.bytes.resize(16, 0);
+
+    output.u16(kind);
+    output.u16(EM_RISCV);
+    output.u32(1);
+    output.word(entry);
+    output.word(if program_headers > 0 {
+        header_size(xlen)
+    } else {
+        0
+    });
+    // The offset of the section headers
+    output.word(0);
+    output.u32(flags);
+    output.u16(header_size(xlen) as u16);
+    output.u16(if program_headers > 0 {
+        PROGRAM_HEADER_SIZE as u16
+    } else {
+        0
+    });
+    output.u16(program_headers);
+    output.u16(section_header_size(xlen) as u16);
+    // The number of section headers and the index of the section name table
+    output.u16(0);
+    output.u16(0);
+}
+
+/// Write the section name table and the section headers, and fill in their location in the
+/// ELF header
+fn finish(mut output: Writer, mut sections: Vec<SectionHeader>) -> Vec<u8> {
+    sections.push(SectionHeader {
+        name: ".shstrtab",
+        kind: SHT_STRTAB,
+        alignment: 1,
+        ..SectionHeader::default()
+    });
+
+    let mut names = StringTable::new();
+    let name_offsets: Vec<u32> = sections
+        .iter()
+        .map(|section| names.add(section.name))
+        .collect();
+    let last = sections.len() - 1;
+    sections[last].offset = output.offset();
+    sections[last].size = names.bytes.len() as u64;
+    output.bytes.extend_from_slice(&names.bytes);
+
+    let xlen = output.xlen;
+    output.align(8);
+    let header_offset = output.offset();
+
+    // The null section comes first
+    output.zeros(section_header_size(xlen));
+    for (section, name) in sections.iter().zip(name_offsets) {
+        output.u32(name);
+        output.u32(section.kind);
+        output.word(section.flags);
+        output.word(section.address);
+        output.word(section.offset);
+        output.word(section.size);
+        output.u32(section.link);
+        output.u32(section.info);
+        output.word(section.alignment);
+        output.word(section.entry_size);
+    }
+
+    // The section header offset follows the entry point and program header offset, and the
+    // counts are the last fields of the header
+    let header_offset_field = match xlen {
+        Xlen::Rv32 => 32,
+        Xlen::Rv64 => 40,
+    };
+    output.patch_word(header_offset_field, header_offset);
+    let end = header_size(xlen) as usize;
+    let count = sections.len() as u16 + 1;
+    output.bytes[end - 4..end - 2].copy_from_slice(&count.to_le_bytes());
+    output.bytes[end - 2..end].copy_from_slice(&(count - 1).to_le_bytes());
+    output.bytes
+}
+
+/// Write an ELF64 executable for Linux that loads an RV64 program at the addresses that it was
+/// assembled for and starts at its entry point.
+///
+/// The text section is mapped read-only and executable, and the data and bss sections follow
+/// it in a writable segment. The bss section, which holds the tape, takes up no space in the file
+/// and is zeroed by the loader.
+///
+/// # Panics
+///
+/// Panics if the program was not assembled for RV64.
+pub fn executable(program: &Program) -> Vec<u8> {
+    assert_eq!(
+        program.xlen,
+        Xlen::Rv64,
+        "ELF executables are only written for RV64"
+    );
+
+    let end = program.bss_base + program.bss_size;
+    let writable = end > program.data_base;
+    let text_offset = PAGE_SIZE;
+    let data_offset = text_offset + (program.data_base - TEXT_BASE);
+
+    let mut output = Writer::new(Xlen::Rv64);
+    write_header(
+        &mut output,
+        ET_EXEC,
+        program.entry,
+        1 + writable as u16,
+        flags(program),
+    );
+
+    // The program headers
+    let mut segment = |offset, address, file_size, memory_size, flags| {
+        output.u32(PT_LOAD);
+        output.u32(flags);
+        output.u64(offset);
+        output.u64(address);
+        output.u64(address);
+        output.u64(file_size);
+        output.u64(memory_size);
+        output.u64(PAGE_SIZE);
+    };
+    segment(
+        text_offset,
+        TEXT_BASE,
+        program.text_size,
+        program.text_size,
+        PF_R | PF_X,
+    );
+    if writable {
+        segment(
+            data_offset,
+            program.data_base,
+            program.data_size,
+            end - program.data_base,
+            PF_R | PF_W,
+        );
+    }
+
+    // The contents of the text and data sections, at the offsets given in the program headers
+    let mut sections = Vec::new();
+    output.pad_to(text_offset);
+    output
+        .bytes
+        .extend_from_slice(&program.image[..program.text_size as usize]);
+    sections.push(SectionHeader {
+        name: ".text",
+        kind: SHT_PROGBITS,
+        flags: SHF_ALLOC | SHF_EXECINSTR,
+        address: TEXT_BASE,
+        offset: text_offset,
+        size: program.text_size,
+        alignment: 4,
+        ..SectionHeader::default()
+    });
+    let text_index = sections.len() as u16;
+
+    // The writable segment starts within the file even if it has no data
+    if writable {
+        output.pad_to(data_offset);
+    }
+    let mut data_index = None;
+    if program.data_size > 0 {
+        let start = (program.data_base - TEXT_BASE) as usize;
+        output
+            .bytes
+            .extend_from_slice(&program.image[start..start + program.data_size as usize]);
+        sections.push(SectionHeader {
+            name: ".data",
+            kind: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_WRITE,
+            address: program.data_base,
+            offset: data_offset,
+            size: program.data_size,
+            alignment: 8,
+            ..SectionHeader::default()
+        });
+        data_index = Some(sections.len() as u16);
+    }
+
+    let mut bss_index = None;
+    if program.bss_size > 0 {
+        sections.push(SectionHeader {
+            name: ".bss",
+            kind: SHT_NOBITS,
+            flags: SHF_ALLOC | SHF_WRITE,
+            address: program.bss_base,
+            offset: output.offset(),
+            size: program.bss_size,
+            alignment: 8,
+            ..SectionHeader::default()
+        });
+        bss_index = Some(sections.len() as u16);
+    }
+
+    // Every label, so that debuggers and disassemblers can show them
+    let section_of = |address: u64| match (bss_index, data_index) {
+        (Some(index), _) if address >= program.bss_base => index,
+        (_, Some(index)) if address >= program.data_base => index,
+        _ => text_index,
+    };
+    let symbols = symbols(program, section_of, 0);
+    write_symbol_table(&mut output, &mut sections, &symbols);
+
+    finish(output, sections)
+}
+
+/// The header flags that describe the instructions that a program uses
+fn flags(program: &Program) -> u32 {
+    if program.compressed > 0 {
+        EF_RISCV_RVC
+    } else {
+        0
+    }
+}
+
+/// The labels of a program, at their address less `base`, in order of address. The globals in
+/// the text section are functions.
+fn symbols(program: &Program, section_of: impl Fn(u64) -> u16, base: u64) -> Vec<Symbol> {
+    let text_end = TEXT_BASE + program.text_size;
+    let mut symbols: Vec<Symbol> = program
+        .symbols
+        .iter()
+        .map(|(name, address)| {
+            let global = program.globals.contains(name);
+            Symbol {
+                name: name.clone(),
+                value: address - base,
+                section: section_of(*address),
+                global,
+                function: global && *address < text_end,
+                size: 0,
+            }
+        })
+        .collect();
+    symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+
+    // Each function runs until the next one starts, or the end of the text section
+    let mut end = text_end - base;
+    for symbol in symbols.iter_mut().rev().filter(|symbol| symbol.function) {
+        symbol.size = end - symbol.value;
+        end = symbol.value;
+    }
+    symbols
+}
+
+/// Write the symbol table and its string table, and add their section headers
+fn write_symbol_table(output: &mut Writer, sections: &mut Vec<SectionHeader>, symbols: &[Symbol]) {
+    output.align(8);
+    let symbol_offset = output.offset();
+    let mut names = StringTable::new();
+    let first_global = write_symbols(output, symbols, &mut names);
+    let symbol_index = sections.len() as u32 + 1;
+    sections.push(SectionHeader {
+        name: ".symtab",
+        kind: SHT_SYMTAB,
+        offset: symbol_offset,
+        size: output.offset() - symbol_offset,
+        link: symbol_index + 1,
+        info: first_global,
+        alignment: 8,
+        entry_size: symbol_size(output.xlen),
+        ..SectionHeader::default()
+    });
+    sections.push(SectionHeader {
+        name: ".strtab",
+        kind: SHT_STRTAB,
+        offset: output.offset(),
+        size: names.bytes.len() as u64,
+        alignment: 1,
+        ..SectionHeader::default()
+    });
+    output.bytes.extend_from_slice(&names.bytes);
+}
+
+/// Write a relocatable ELF object file holding the text section of a program, which can be
+/// linked into a C or Rust program. ELF32 files are written for RV32 programs, and ELF64 files
+/// for RV64 programs. The symbols declared with `.globl` can be called from other files.
+///
+/// Only position-independent code can be written, since the file has no relocations: every
+/// jump and address must be relative to the program counter.
+///
+/// # Panics
+///
+/// Panics if the program has a data or bss section.
+pub fn relocatable(program: &Program, float_abi: FloatAbi) -> Vec<u8> {
+    assert!(
+        program.data_size == 0 && program.bss_size == 0,
+        "object files are only written for programs without data"
+    );
+
+    let mut output = Writer::new(program.xlen);
+    write_header(
+        &mut output,
+        ET_REL,
+        0,
+        0,
+        flags(program) | float_abi.flags(),
+    );
+
+    // The text section is linked at an address chosen by the linker, so it is written at 0
+    let mut sections = Vec::new();
+    output.align(8);
+    let text_offset = output.offset();
+    output
+        .bytes
+        .extend_from_slice(&program.image[..program.text_size as usize]);
+    sections.push(SectionHeader {
+        name: ".text",
+        kind: SHT_PROGBITS,
+        flags: SHF_ALLOC | SHF_EXECINSTR,
+        offset: text_offset,
+        size: program.text_size,
+        alignment: 4,
+        ..SectionHeader::default()
+    });
+    let text_index = sections.len() as u16;
+
+    // The stack does not need to be executable
+    sections.push(SectionHeader {
+        name: ".note.GNU-stack",
+        kind: SHT_PROGBITS,
+        offset: output.offset(),
+        alignment: 1,
+        ..SectionHeader::default()
+    });
+
+    let symbols = symbols(program, |_| text_index, TEXT_BASE);
+    write_symbol_table(&mut output, &mut sections, &symbols);
+
+    finish(output, sections)
+}
@@ -0,0 +1,305 @@
+/// A xorshift64* pseudo-random number generator, which is small, fast, and good enough for
+/// generating test programs
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Constructs a generator from a seed. Every seed, including 0, gives a different sequence.
+    pub fn new(seed: u64) -> Self {
+        // Xorshift gets stuck at 0, so mix the seed with a SplitMix64 step first
+        let mut state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// The next number in the sequence
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number from 0 up to but not including `bound`, which must not be 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// A number from `low` up to and including `high`
+    pub fn range(&mut self, low: usize, high: usize) -> usize {
+        low + self.below(high - low + 1)
+    }
+
+    /// Returns true with a probability of `numerator` in `denominator`
+    pub fn chance(&mut self, numerator: usize, denominator: usize) -> bool {
+        self.below(denominator) < numerator
+    }
+}
+
+/// The shape of the programs to generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// The most instructions in the body of the program or of a loop, counting loops as one
+    pub max_block_length: usize,
+    /// The most loops that can be nested inside each other
+    pub max_depth: usize,
+    /// The most times any one loop runs each time it is reached
+    pub max_iterations: usize,
+    /// The number of cells that the program uses, starting at the first cell of the tape
+    pub cells: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_block_length: 12,
+            max_depth: 4,
+            max_iterations: 5,
+            cells: 16,
+        }
+    }
+}
+
+/// How a cell has been used so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    /// Never used, so it holds 0
+    Fresh,
+    /// Modified or read into, so it may hold any value
+    Data,
+    /// Only ever used as a loop counter or the target of a scan, so it holds 0 outside of its
+    /// loop
+    Counter,
+}
+
+/// Generates well-formed Brainfuck programs that terminate with 8-bit and 16-bit cells, whatever
+/// their input.
+///
+/// Most loops are counted loops over a cell that is used for nothing else, which is set to at
+/// most `max_iterations` before the loop and decremented once in each iteration. The body
+/// never touches the counters of the loops around it, and returns the pointer to the counter
+/// before the end of the loop. The pointer stays within the first `cells` cells of the tape.
+///
+/// The other loops are clear loops such as `[-]` on data cells, which reach zero from any
+/// value, and scan loops such as `[>]` and `[<<]` towards a cell that is known to hold 0, over
+/// cells that are first set to 1.
+///
+/// A clear loop runs once for each step from the cell's value to zero in its direction, so
+/// `[+]` on a positive cell or `[-]` on a cell that went below zero runs up to 65535 times with
+/// 16-bit cells. With 32-bit and 64-bit cells, such a loop does not finish in any reasonable
+/// time.
+pub struct Generator {
+    rng: Rng,
+    config: Config,
+    output: String,
+    cells: Vec<Cell>,
+    pointer: usize,
+    /// The counters of the loops that are currently open
+    counters: Vec<usize>,
+}
+
+impl Generator {
+    /// Constructs a Generator that produces the same programs for the same seed and config
+    pub fn new(seed: u64, config: Config) -> Self {
+        Generator {
+            rng: Rng::new(seed),
+            config,
+            output: String::new(),
+            cells: vec![Cell::Fresh; config.cells],
+            pointer: 0,
+            counters: Vec::new(),
+        }
+    }
+
+    /// Generate a program
+    pub fn generate(mut self) -> String {
+        self.block();
+        self.output
+    }
+
+    /// Generate a sequence of instructions and loops
+    fn block(&mut self) {
+        let length = self.rng.range(1, self.config.max_block_length);
+        for _ in 0..length {
+            match self.rng.below(12) {
+                0..=1 => {
+                    let cell = self.rng.below(self.config.cells);
+                    self.move_to(cell);
+                }
+                2..=4 => self.add(),
+                5 => self.write(),
+                6 => self.read(),
+                7 => self.comment(),
+                8 => self.clear_loop(),
+                9 => self.scan_loop(),
+                _ => self.counted_loop(),
+            }
+        }
+    }
+
+    /// Move the pointer to the given cell
+    fn move_to(&mut self, cell: usize) {
+        let step = if cell > self.pointer { '>' } else { '<' };
+        let distance = cell.abs_diff(self.pointer);
+        self.output.extend(std::iter::repeat_n(step, distance));
+        self.pointer = cell;
+    }
+
+    /// Move to a cell that the program can freely change, if there is one
+    fn move_to_data(&mut self) -> bool {
+        let candidates: Vec<usize> = (0..self.config.cells)
+            .filter(|cell| self.cells[*cell] != Cell::Counter)
+            .collect();
+        if candidates.is_empty() {
+            return false;
+        }
+
+        let cell = candidates[self.rng.below(candidates.len())];
+        self.move_to(cell);
+        self.cells[cell] = Cell::Data;
+        true
+    }
+
+    /// Add to or subtract from a data cell, sometimes enough to wrap around
+    fn add(&mut self) {
+        if !self.move_to_data() {
+            return;
+        }
+
+        let op = if self.rng.chance(1, 2) { '+' } else { '-' };
+        let count = if self.rng.chance(1, 8) {
+            self.rng.range(250, 300)
+        } else {
+            self.rng.range(1, 20)
+        };
+        self.output.extend(std::iter::repeat_n(op, count));
+    }
+
+    /// Write the current cell, which may be of any kind
+    fn write(&mut self) {
+        let count = self.rng.range(1, 2);
+        self.output.extend(std::iter::repeat_n('.', count));
+    }
+
+    /// Read into a data cell
+    fn read(&mut self) {
+        if self.move_to_data() {
+            self.output.push(',');
+        }
+    }
+
+    /// Add characters that are not instructions
+    fn comment(&mut self) {
+        const COMMENTS: &[&str] = &["\n", " ", "loop", "# x", "\t", "e\u{301}"];
+        self.output
+            .push_str(COMMENTS[self.rng.below(COMMENTS.len())]);
+    }
+
+    /// Clear a data cell. Decrementing or incrementing by one reaches zero from any value.
+    fn clear_loop(&mut self) {
+        if self.move_to_data() {
+            let clear = if self.rng.chance(1, 2) { "[-]" } else { "[+]" };
+            self.output.push_str(clear);
+        }
+    }
+
+    /// Generate a loop that moves the pointer to a cell that holds 0, over up to 12 cells that
+    /// are set to 1 first so that the loop cannot stop before it
+    fn scan_loop(&mut self) {
+        // Cells hold 0 if they were never used, or are the counters of loops that are not open
+        let zeros: Vec<usize> = (0..self.config.cells)
+            .filter(|cell| match self.cells[*cell] {
+                Cell::Fresh => true,
+                Cell::Counter => !self.counters.contains(cell),
+                Cell::Data => false,
+            })
+            .collect();
+        if zeros.is_empty() {
+            return;
+        }
+        let target = zeros[self.rng.below(zeros.len())];
+
+        // Inside a loop, the scan runs again after the rest of the body, which must not change
+        // the target
+        self.cells[target] = Cell::Counter;
+        let stride = self.rng.range(1, 3);
+        let forward = self.rng.chance(1, 2);
+
+        // The cells that the scan passes over, starting next to the target, which must not be
+        // counters. The scan starts at the farthest one.
+        let mut path = Vec::new();
+        for step in 1..=self.rng.range(0, 12) {
+            let cell = if forward {
+                target.checked_sub(step * stride)
+            } else {
+                Some(target + step * stride).filter(|cell| *cell < self.config.cells)
+            };
+            match cell {
+                Some(cell) if self.cells[cell] != Cell::Counter => path.push(cell),
+                _ => break,
+            }
+        }
+        for cell in path.iter().rev() {
+            self.move_to(*cell);
+            self.cells[*cell] = Cell::Data;
+            self.output.push_str("[-]+");
+        }
+        self.move_to(path.last().copied().unwrap_or(target));
+
+        let step = if forward { '>' } else { '<' };
+        self.output.push('[');
+        self.output.extend(std::iter::repeat_n(step, stride));
+        self.output.push(']');
+        self.pointer = target;
+    }
+
+    /// Generate a loop that runs a fixed number of times, with a random body
+    fn counted_loop(&mut self) {
+        if self.counters.len() >= self.config.max_depth {
+            return;
+        }
+
+        // Counters hold 0 outside of their loops, so any counter not in use can be reused
+        let candidates: Vec<usize> = (0..self.config.cells)
+            .filter(|cell| self.cells[*cell] != Cell::Data && !self.counters.contains(cell))
+            .collect();
+        if candidates.is_empty() {
+            return;
+        }
+        let counter = candidates[self.rng.below(candidates.len())];
+        self.cells[counter] = Cell::Counter;
+
+        self.move_to(counter);
+        let iterations = self.rng.range(1, self.config.max_iterations);
+        self.output.extend(std::iter::repeat_n('+', iterations));
+        self.output.push('[');
+
+        // Decrementing first gives the multiply loop optimization something to find
+        let decrement_first = self.rng.chance(1, 2);
+        if decrement_first {
+            self.output.push('-');
+        }
+        self.counters.push(counter);
+        if self.rng.chance(3, 4) {
+            self.block();
+        }
+        self.counters.pop();
+        self.move_to(counter);
+        if !decrement_first {
+            self.output.push('-');
+        }
+        self.output.push(']');
+    }
+}
+
+/// Generate a program from a seed. See [`Generator`] for the kinds of program generated.
+pub fn generate(seed: u64, config: Config) -> String {
+    Generator::new(seed, config).generate()
+}
@@ -1,39 +1,50 @@
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     AddPtr { count: usize },
     SubPtr { count: usize },
-    AddByte { count: usize },
-    SubByte { count: usize },
+    AddByte { offset: isize, delta: u64 },
     Read { count: usize },
     Write { count: usize },
     LoopStart { end: usize },
     LoopEnd { start: usize },
+    SetByte { offset: isize, value: u64 },
+    MulAdd { offset: isize, factor: u64 },
+    Scan { stride: isize },
 }
 
 impl Instruction {
-    /// Increment the count field of this Instruction
-    pub fn increment(&mut self) {
-        match self {
-            Instruction::AddPtr { count } => *count += 1,
-            Instruction::SubPtr { count } => *count += 1,
-            Instruction::AddByte { count } => *count += 1,
-            Instruction::SubByte { count } => *count += 1,
-            Instruction::Read { count } => *count += 1,
-            Instruction::Write { count } => *count += 1,
-            _ => panic!("increment_count() called on instruction without count"),
-        }
-    }
-
-    /// Indicates whether this Instruction has a count field that can be incremented
-    pub fn can_increment(&self) -> bool {
-        match self {
-            Instruction::AddPtr { .. }
-            | Instruction::SubPtr { .. }
-            | Instruction::AddByte { .. }
-            | Instruction::SubByte { .. }
-            | Instruction::Read { .. }
-            | Instruction::Write { .. } => true,
-            Instruction::LoopStart { .. } | Instruction::LoopEnd { .. } => false,
+    /// Combines `next` into this Instruction, if doing both is equivalent to a single Instruction.
+    /// Returns whether `next` was combined.
+    pub fn combine(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (Instruction::AddPtr { count }, Instruction::AddPtr { count: next })
+            | (Instruction::SubPtr { count }, Instruction::SubPtr { count: next })
+            | (Instruction::Read { count }, Instruction::Read { count: next })
+            | (Instruction::Write { count }, Instruction::Write { count: next }) => {
+                *count += next;
+                true
+            }
+            (
+                Instruction::AddByte { offset, delta },
+                Instruction::AddByte {
+                    offset: next_offset,
+                    delta: next,
+                },
+            ) if offset == next_offset => {
+                *delta = delta.wrapping_add(*next);
+                true
+            }
+            (
+                Instruction::SetByte { offset, value },
+                Instruction::AddByte {
+                    offset: next_offset,
+                    delta,
+                },
+            ) if offset == next_offset => {
+                *value = value.wrapping_add(*delta);
+                true
+            }
+            _ => false,
         }
     }
 
@@ -42,8 +53,14 @@ impl Instruction {
         match c {
             '>' => Some(Instruction::AddPtr { count: 1 }),
             '<' => Some(Instruction::SubPtr { count: 1 }),
-            '+' => Some(Instruction::AddByte { count: 1 }),
-            '-' => Some(Instruction::SubByte { count: 1 }),
+            '+' => Some(Instruction::AddByte {
+                offset: 0,
+                delta: 1,
+            }),
+            '-' => Some(Instruction::AddByte {
+                offset: 0,
+                delta: u64::MAX,
+            }),
             ',' => Some(Instruction::Read { count: 1 }),
             '.' => Some(Instruction::Write { count: 1 }),
             '[' => Some(Instruction::LoopStart { end: 0 }),
@@ -60,8 +77,4 @@ impl Instruction {
             _ => panic!("set_target() called on instruction with no target"),
         }
     }
-
-    pub fn variant_eq(&self, other: &Self) -> bool {
-        std::mem::discriminant(self) == std::mem::discriminant(other)
-    }
 }
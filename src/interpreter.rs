@@ -0,0 +1,200 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    dialect::{Dialect, EofBehavior},
+    instruction::Instruction,
+};
+
+/// The error returned when a program stops abnormally
+#[derive(Debug)]
+pub enum InterpretError {
+    /// The instruction at this index accessed a cell before the start of the tape
+    Underflow(usize),
+    /// The instruction at this index accessed a cell past the end of the tape
+    Overflow(usize),
+    /// The program ran for more instructions than allowed
+    StepLimit(u64),
+    /// Reading input or writing output failed
+    Io(io::Error),
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpretError::Underflow(index) => {
+                write!(f, "pointer underflow at instruction {}", index)
+            }
+            InterpretError::Overflow(index) => {
+                write!(f, "pointer overflow at instruction {}", index)
+            }
+            InterpretError::StepLimit(steps) => {
+                write!(f, "program did not finish after {} instructions", steps)
+            }
+            InterpretError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for InterpretError {}
+
+impl From<io::Error> for InterpretError {
+    fn from(error: io::Error) -> Self {
+        InterpretError::Io(error)
+    }
+}
+
+/// Executes a program directly, without compiling it.
+///
+/// The pointer may move off the tape as long as no cell is accessed there, so that programs
+/// behave the same before and after their pointer moves are optimized.
+pub struct Interpreter<'a> {
+    program: &'a [Instruction],
+    dialect: Dialect,
+    tape: Vec<u64>,
+    pointer: isize,
+    /// The number of instructions executed so far
+    pub steps: u64,
+    /// The number of instructions after which to stop, if any
+    pub step_limit: Option<u64>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Constructs an Interpreter that is ready to run `program` with a zeroed tape.
+    /// The targets of the program's loops must be linked.
+    pub fn new(program: &'a [Instruction], dialect: Dialect) -> Self {
+        Interpreter {
+            program,
+            dialect,
+            tape: vec![0; dialect.tape_length],
+            pointer: dialect.tape_start as isize,
+            steps: 0,
+            step_limit: None,
+        }
+    }
+
+    /// Run the program to the end, reading from `input` and writing to `output`
+    pub fn run(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+    ) -> Result<(), InterpretError> {
+        let width = self.dialect.cell_width;
+        let mut index = 0;
+
+        while index < self.program.len() {
+            if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+                return Err(InterpretError::StepLimit(self.steps));
+            }
+            self.steps += 1;
+
+            match self.program[index] {
+                Instruction::AddPtr { count } => self.pointer += count as isize,
+                Instruction::SubPtr { count } => self.pointer -= count as isize,
+                Instruction::AddByte { offset, delta } => {
+                    let cell = self.cell(index, offset)?;
+                    *cell = width.wrap(cell.wrapping_add(delta));
+                }
+                Instruction::SetByte { offset, value } => {
+                    *self.cell(index, offset)? = width.wrap(value);
+                }
+                Instruction::MulAdd { offset, factor } => {
+                    let value = *self.cell(index, 0)?;
+                    let cell = self.cell(index, offset)?;
+                    *cell = width.wrap(cell.wrapping_add(value.wrapping_mul(factor)));
+                }
+                Instruction::Scan { stride } => {
+                    while *self.cell(index, 0)? != 0 {
+                        self.pointer += stride;
+                    }
+                }
+                Instruction::Read { count } => {
+                    // Show any prompt before waiting for input
+                    output.flush()?;
+                    self.read(index, count, input)?;
+                }
+                Instruction::Write { count } => {
+                    let byte = *self.cell(index, 0)? as u8;
+                    for _ in 0..count {
+                        output.write_all(&[byte])?;
+                    }
+                }
+                Instruction::LoopStart { end } => {
+                    if *self.cell(index, 0)? == 0 {
+                        index = end;
+                    }
+                }
+                Instruction::LoopEnd { start } => {
+                    if *self.cell(index, 0)? != 0 {
+                        index = start;
+                    }
+                }
+            }
+
+            index += 1;
+        }
+
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Read `count` bytes into the current cell, keeping the last. Once the end of input is
+    /// reached, the remaining reads are skipped and the cell is updated according to the EOF
+    /// behavior.
+    fn read(
+        &mut self,
+        index: usize,
+        count: usize,
+        input: &mut dyn Read,
+    ) -> Result<(), InterpretError> {
+        let eof = self.dialect.eof;
+        let width = self.dialect.cell_width;
+        let cell = self.cell(index, 0)?;
+
+        for _ in 0..count {
+            let mut byte = [0];
+            let read = loop {
+                match input.read(&mut byte) {
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            };
+
+            if read == 0 {
+                match eof {
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::Zero => *cell = 0,
+                    EofBehavior::MinusOne => *cell = width.wrap(u64::MAX),
+                }
+                break;
+            }
+            *cell = byte[0] as u64;
+        }
+        Ok(())
+    }
+
+    /// The cell `offset` cells away from the pointer, accessed by the instruction at `index`
+    fn cell(&mut self, index: usize, offset: isize) -> Result<&mut u64, InterpretError> {
+        let position = self.pointer + offset;
+        if position < 0 {
+            return Err(InterpretError::Underflow(index));
+        }
+        self.tape
+            .get_mut(position as usize)
+            .ok_or(InterpretError::Overflow(index))
+    }
+}
+
+/// Run a program with `input` as its input, returning its output
+pub fn run(
+    program: &[Instruction],
+    dialect: Dialect,
+    input: &[u8],
+) -> Result<Vec<u8>, InterpretError> {
+    let mut output = Vec::new();
+    Interpreter::new(program, dialect).run(&mut &input[..], &mut output)?;
+    Ok(output)
+}
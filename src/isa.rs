@@ -0,0 +1,374 @@
+use std::fmt;
+
+pub mod compressed;
+pub mod decoder;
+pub mod encoder;
+
+pub use compressed::{compress, expand, COMPRESSED_LENGTH};
+pub use decoder::decode;
+pub use encoder::{encode, EncodeError, Pending};
+
+/// The length of every instruction in bytes
+pub const INSTRUCTION_LENGTH: u64 = 4;
+
+/// The width of the integer registers, which is 32 bits for RV32 and 64 bits for RV64
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xlen {
+    Rv32,
+    Rv64,
+}
+
+impl Xlen {
+    /// The register width of the target with the given name, if it is a RISC-V target
+    pub fn for_target(target: &str) -> Option<Self> {
+        match target {
+            "riscv32" => Some(Xlen::Rv32),
+            "riscv64" => Some(Xlen::Rv64),
+            _ => None,
+        }
+    }
+
+    /// The number of bits in a register
+    pub fn bits(self) -> u32 {
+        match self {
+            Xlen::Rv32 => 32,
+            Xlen::Rv64 => 64,
+        }
+    }
+}
+
+/// The names of the ISAs that code can be generated for
+pub const ARCH_NAMES: &[&str] = &["rv32i", "rv32im", "rv64i", "rv64gc"];
+
+/// A base ISA and the standard extensions that code may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arch {
+    pub xlen: Xlen,
+    /// The M extension, which provides multiplication and division
+    pub m: bool,
+    /// The A extension, which provides atomic instructions
+    pub a: bool,
+    /// The F and D extensions, which provide single and double-precision floating point
+    pub f: bool,
+    pub d: bool,
+    /// The C extension, which provides 16-bit encodings of common instructions
+    pub c: bool,
+}
+
+impl Arch {
+    /// The base ISA with no extensions
+    pub fn base(xlen: Xlen) -> Self {
+        Arch {
+            xlen,
+            m: false,
+            a: false,
+            f: false,
+            d: false,
+            c: false,
+        }
+    }
+
+    /// The ISA assumed for the register width when none is given: RV32IM, which RARS
+    /// simulates, or RV64GC, which Linux distributions require
+    pub fn default_for(xlen: Xlen) -> Self {
+        match xlen {
+            Xlen::Rv32 => Arch {
+                m: true,
+                ..Arch::base(xlen)
+            },
+            Xlen::Rv64 => Arch {
+                m: true,
+                a: true,
+                f: true,
+                d: true,
+                c: true,
+                ..Arch::base(xlen)
+            },
+        }
+    }
+
+    /// Looks up one of the ISAs in `ARCH_NAMES`
+    pub fn from_name(name: &str) -> Option<Self> {
+        if ARCH_NAMES.contains(&name) {
+            Arch::parse(name)
+        } else {
+            None
+        }
+    }
+
+    /// Parses an ISA string, such as `rv64gc` or `rv32i2p1_m2p0`. Extension versions and
+    /// multi-letter extensions are ignored.
+    pub fn parse(isa: &str) -> Option<Self> {
+        let isa = isa.to_ascii_lowercase();
+        let (xlen, rest) = if let Some(rest) = isa.strip_prefix("rv32") {
+            (Xlen::Rv32, rest)
+        } else {
+            (Xlen::Rv64, isa.strip_prefix("rv64")?)
+        };
+        if !rest.starts_with(['i', 'e', 'g']) {
+            return None;
+        }
+
+        let mut arch = Arch::base(xlen);
+        for (index, part) in rest.split('_').enumerate() {
+            // Only the first part can hold several single-letter extensions
+            if index > 0 && part.starts_with(['z', 's', 'x']) {
+                continue;
+            }
+            for letter in part.chars().filter(char::is_ascii_alphabetic) {
+                match letter {
+                    'm' => arch.m = true,
+                    'a' => arch.a = true,
+                    'f' => arch.f = true,
+                    'd' => arch.d = true,
+                    'c' => arch.c = true,
+                    'g' => {
+                        arch.m = true;
+                        arch.a = true;
+                        arch.f = true;
+                        arch.d = true;
+                    }
+                    // Version numbers are written as `2p1`
+                    _ => {}
+                }
+                if index > 0 {
+                    break;
+                }
+            }
+        }
+        Some(arch)
+    }
+
+    /// The shortest ISA string naming the base ISA and extensions
+    pub fn name(self) -> String {
+        let mut name = format!("rv{}", self.xlen.bits());
+        if self.m && self.a && self.f && self.d {
+            name.push('g');
+        } else {
+            name.push('i');
+            for (enabled, letter) in [(self.m, 'm'), (self.a, 'a'), (self.f, 'f'), (self.d, 'd')] {
+                if enabled {
+                    name.push(letter);
+                }
+            }
+        }
+        if self.c {
+            name.push('c');
+        }
+        name
+    }
+
+    /// Whether an instruction is part of the ISA. Instructions that only exist on RV64 are
+    /// rejected when they are encoded instead.
+    pub fn supports(self, inst: Inst) -> bool {
+        match inst {
+            Inst::Op { op, .. } => self.m || !op.is_m_extension(),
+            _ => true,
+        }
+    }
+}
+
+/// One of the 32 integer registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Register(pub u8);
+
+/// The ABI names of the integer registers, indexed by register number
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl Register {
+    pub const ZERO: Register = Register(0);
+    pub const RA: Register = Register(1);
+    pub const SP: Register = Register(2);
+    pub const A0: Register = Register(10);
+    pub const A1: Register = Register(11);
+    pub const A2: Register = Register(12);
+    pub const T1: Register = Register(6);
+    pub const A7: Register = Register(17);
+
+    /// Constructs a Register from its ABI name (`s0`), its number (`x8`), or `fp`
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "fp" {
+            return Some(Register(8));
+        }
+        if let Some(number) = ABI_NAMES.iter().position(|abi| *abi == name) {
+            return Some(Register(number as u8));
+        }
+        name.strip_prefix('x')
+            .and_then(|number| number.parse().ok())
+            .filter(|number| *number < 32)
+            .map(Register)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", ABI_NAMES[self.0 as usize])
+    }
+}
+
+/// Register-register and register-immediate arithmetic operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+impl AluOp {
+    /// Whether the operation is part of the M extension
+    pub fn is_m_extension(self) -> bool {
+        matches!(
+            self,
+            AluOp::Mul
+                | AluOp::Mulh
+                | AluOp::Mulhsu
+                | AluOp::Mulhu
+                | AluOp::Div
+                | AluOp::Divu
+                | AluOp::Rem
+                | AluOp::Remu
+        )
+    }
+}
+
+/// Conditional branch comparisons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchOp {
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+}
+
+/// Memory access widths, and whether loads sign-extend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    ByteUnsigned,
+    Half,
+    HalfUnsigned,
+    Word,
+    WordUnsigned,
+    Double,
+}
+
+impl Width {
+    /// The number of bytes accessed
+    pub fn bytes(self) -> u64 {
+        match self {
+            Width::Byte | Width::ByteUnsigned => 1,
+            Width::Half | Width::HalfUnsigned => 2,
+            Width::Word | Width::WordUnsigned => 4,
+            Width::Double => 8,
+        }
+    }
+
+    /// Whether a load of this width sign-extends the value
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            Width::Byte | Width::Half | Width::Word | Width::Double
+        )
+    }
+}
+
+/// A single RV32IM or RV64IM instruction, with pseudo-instructions already expanded and
+/// labels already resolved to offsets in bytes from the instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inst {
+    /// Load the upper immediate, `imm << 12`
+    Lui {
+        rd: Register,
+        imm: i64,
+    },
+    /// Add the upper immediate, `imm << 12`, to the address of this instruction
+    Auipc {
+        rd: Register,
+        imm: i64,
+    },
+    Jal {
+        rd: Register,
+        offset: i64,
+    },
+    Jalr {
+        rd: Register,
+        rs1: Register,
+        offset: i64,
+    },
+    Branch {
+        op: BranchOp,
+        rs1: Register,
+        rs2: Register,
+        offset: i64,
+    },
+    Load {
+        width: Width,
+        rd: Register,
+        rs1: Register,
+        offset: i64,
+    },
+    Store {
+        width: Width,
+        rs1: Register,
+        rs2: Register,
+        offset: i64,
+    },
+    /// An operation with an immediate operand. `word` selects the RV64 `*w` form, which
+    /// operates on the low 32 bits and sign-extends the result.
+    OpImm {
+        op: AluOp,
+        rd: Register,
+        rs1: Register,
+        imm: i64,
+        word: bool,
+    },
+    /// An operation with two register operands. `word` selects the RV64 `*w` form.
+    Op {
+        op: AluOp,
+        rd: Register,
+        rs1: Register,
+        rs2: Register,
+        word: bool,
+    },
+    Ecall,
+}
+
+/// Split a 32-bit offset into the upper 20 bits for `lui` or `auipc` and the lower 12 bits for
+/// the instruction that follows, which sign-extends them
+pub fn split_offset(offset: i64) -> (i64, i64) {
+    let low = sign_extend(offset, 12);
+    let high = sign_extend((offset - low) >> 12, 20);
+    (high, low)
+}
+
+/// Sign-extend the lowest `bits` bits of `value`
+pub fn sign_extend(value: i64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    (value << shift) >> shift
+}
+
+/// Whether `value` fits in a signed 12-bit immediate
+pub fn fits_immediate(value: i64) -> bool {
+    (-2048..2048).contains(&value)
+}
@@ -0,0 +1,466 @@
+use super::{sign_extend, AluOp, BranchOp, Inst, Register, Width, Xlen};
+
+/// The length of a compressed instruction in bytes
+pub const COMPRESSED_LENGTH: u64 = 2;
+
+/// Marks an instruction bit that is not part of the immediate
+const X: u32 = u32::MAX;
+
+/// The bit of the immediate held by each instruction bit, starting from bit 12, in each of the
+/// immediate formats of the C extension
+const CI: &[u32] = &[5, X, X, X, X, X, 4, 3, 2, 1, 0];
+const CI_ADDI16SP: &[u32] = &[9, X, X, X, X, X, 4, 6, 8, 7, 5];
+const CI_LWSP: &[u32] = &[5, X, X, X, X, X, 4, 3, 2, 7, 6];
+const CI_LDSP: &[u32] = &[5, X, X, X, X, X, 4, 3, 8, 7, 6];
+const CSS_SWSP: &[u32] = &[5, 4, 3, 2, 7, 6];
+const CSS_SDSP: &[u32] = &[5, 4, 3, 8, 7, 6];
+const CIW: &[u32] = &[5, 4, 9, 8, 7, 6, 2, 3];
+const CL_WORD: &[u32] = &[5, 4, 3, X, X, X, 2, 6];
+const CL_DOUBLE: &[u32] = &[5, 4, 3, X, X, X, 7, 6];
+const CB: &[u32] = &[8, 4, 3, X, X, X, 7, 6, 2, 1, 5];
+const CJ: &[u32] = &[11, 4, 9, 8, 10, 6, 7, 3, 2, 1, 5];
+
+/// Scatter the bits of an immediate into the instruction bits given by a format
+fn scatter(imm: i64, format: &[u32]) -> u16 {
+    let mut bits = 0;
+    for (index, bit) in format.iter().enumerate() {
+        if *bit != X {
+            bits |= ((imm >> bit) as u16 & 1) << (12 - index);
+        }
+    }
+    bits
+}
+
+/// Gather the bits of an immediate from the instruction bits given by a format, without
+/// sign-extending it
+fn gather(half: u16, format: &[u32]) -> i64 {
+    let mut imm = 0;
+    for (index, bit) in format.iter().enumerate() {
+        if *bit != X {
+            imm |= ((half >> (12 - index)) as i64 & 1) << bit;
+        }
+    }
+    imm
+}
+
+/// The 3-bit number of one of the registers x8 to x15, which are the only registers that most
+/// compressed instructions can use
+fn prime(register: Register) -> Option<u16> {
+    if (8..16).contains(&register.0) {
+        Some(register.0 as u16 - 8)
+    } else {
+        None
+    }
+}
+
+/// The register with a 3-bit number
+fn unprime(bits: u16) -> Register {
+    Register((bits & 0x7) as u8 + 8)
+}
+
+/// Whether `value` fits in a signed immediate of `bits` bits
+fn fits(value: i64, bits: u32) -> bool {
+    sign_extend(value, bits) == value
+}
+
+/// Whether `value` is a multiple of `scale` that fits in an unsigned immediate below `limit`
+fn fits_scaled(value: i64, scale: i64, limit: i64) -> bool {
+    (0..limit).contains(&value) && value % scale == 0
+}
+
+/// Encode an instruction in 16 bits, if the C extension has a form of it for the register
+/// width. Instructions that are not the same as their compressed form, such as `addi` with an
+/// immediate of 0, which is a hint, are not compressed.
+pub fn compress(inst: Inst, xlen: Xlen) -> Option<u16> {
+    let rv64 = xlen == Xlen::Rv64;
+    let full = |register: Register| (register.0 as u16) << 7;
+    let low = |register: Register| (register.0 as u16) << 2;
+    let ci = |funct3: u16, rd: Register, imm: i64, op: u16| {
+        funct3 << 13 | scatter(imm, CI) | full(rd) | op
+    };
+
+    Some(match inst {
+        Inst::Lui { rd, imm }
+            if rd != Register::ZERO && rd != Register::SP && imm != 0 && fits(imm, 6) =>
+        {
+            ci(0b011, rd, imm, 0b01)
+        }
+        Inst::Jal { rd, offset } if fits(offset, 12) && offset % 2 == 0 => {
+            let funct3 = match rd {
+                Register::ZERO => 0b101,
+                Register::RA if !rv64 => 0b001,
+                _ => return None,
+            };
+            funct3 << 13 | scatter(offset, CJ) | 0b01
+        }
+        Inst::Jalr { rd, rs1, offset: 0 } if rs1 != Register::ZERO => {
+            let funct4 = match rd {
+                Register::ZERO => 0b1000,
+                Register::RA => 0b1001,
+                _ => return None,
+            };
+            funct4 << 12 | full(rs1) | 0b10
+        }
+        Inst::Branch {
+            op,
+            rs1,
+            rs2: Register::ZERO,
+            offset,
+        } if fits(offset, 9) && offset % 2 == 0 => {
+            let funct3 = match op {
+                BranchOp::Beq => 0b110,
+                BranchOp::Bne => 0b111,
+                _ => return None,
+            };
+            funct3 << 13 | scatter(offset, CB) | prime(rs1)? << 7 | 0b01
+        }
+        Inst::Load {
+            width,
+            rd,
+            rs1: Register::SP,
+            offset,
+        } if rd != Register::ZERO => match width {
+            Width::Word if fits_scaled(offset, 4, 256) => {
+                0b010 << 13 | scatter(offset, CI_LWSP) | full(rd) | 0b10
+            }
+            Width::Double if rv64 && fits_scaled(offset, 8, 512) => {
+                0b011 << 13 | scatter(offset, CI_LDSP) | full(rd) | 0b10
+            }
+            _ => return None,
+        },
+        Inst::Load {
+            width,
+            rd,
+            rs1,
+            offset,
+        } => {
+            let (funct3, format) = match width {
+                Width::Word if fits_scaled(offset, 4, 128) => (0b010, CL_WORD),
+                Width::Double if rv64 && fits_scaled(offset, 8, 256) => (0b011, CL_DOUBLE),
+                _ => return None,
+            };
+            funct3 << 13 | scatter(offset, format) | prime(rs1)? << 7 | prime(rd)? << 2
+        }
+        Inst::Store {
+            width,
+            rs1: Register::SP,
+            rs2,
+            offset,
+        } => match width {
+            Width::Word if fits_scaled(offset, 4, 256) => {
+                0b110 << 13 | scatter(offset, CSS_SWSP) | low(rs2) | 0b10
+            }
+            Width::Double if rv64 && fits_scaled(offset, 8, 512) => {
+                0b111 << 13 | scatter(offset, CSS_SDSP) | low(rs2) | 0b10
+            }
+            _ => return None,
+        },
+        Inst::Store {
+            width,
+            rs1,
+            rs2,
+            offset,
+        } => {
+            let (funct3, format) = match width {
+                Width::Word if fits_scaled(offset, 4, 128) => (0b110, CL_WORD),
+                Width::Double if rv64 && fits_scaled(offset, 8, 256) => (0b111, CL_DOUBLE),
+                _ => return None,
+            };
+            funct3 << 13 | scatter(offset, format) | prime(rs1)? << 7 | prime(rs2)? << 2
+        }
+        Inst::OpImm {
+            op: AluOp::Add,
+            rd,
+            rs1,
+            imm,
+            word: false,
+        } => {
+            if rd == Register::ZERO {
+                // Only the canonical `nop` has a compressed form
+                if rs1 != Register::ZERO || imm != 0 {
+                    return None;
+                }
+                0b01
+            } else if rs1 == Register::ZERO && fits(imm, 6) {
+                ci(0b010, rd, imm, 0b01)
+            } else if imm == 0 && rs1 != Register::ZERO {
+                // `mv`, which is the same as `add rd, zero, rs1`
+                0b1000 << 12 | full(rd) | low(rs1) | 0b10
+            } else if rd == rs1 && imm != 0 && fits(imm, 6) {
+                ci(0b000, rd, imm, 0b01)
+            } else if rd == Register::SP && rs1 == Register::SP && imm != 0 && fits(imm, 10) {
+                if imm % 16 != 0 {
+                    return None;
+                }
+                0b011 << 13 | scatter(imm, CI_ADDI16SP) | full(rd) | 0b01
+            } else if rs1 == Register::SP && imm != 0 && fits_scaled(imm, 4, 1024) {
+                scatter(imm, CIW) | prime(rd)? << 2
+            } else {
+                return None;
+            }
+        }
+        Inst::OpImm {
+            op: AluOp::Add,
+            rd,
+            rs1,
+            imm,
+            word: true,
+        } if rv64 && rd == rs1 && rd != Register::ZERO && fits(imm, 6) => ci(0b001, rd, imm, 0b01),
+        Inst::OpImm {
+            op,
+            rd,
+            rs1,
+            imm,
+            word: false,
+        } if rd == rs1 => match op {
+            AluOp::Sll if rd != Register::ZERO && imm > 0 && imm < xlen.bits() as i64 => {
+                ci(0b000, rd, imm, 0b10)
+            }
+            AluOp::Srl | AluOp::Sra if imm > 0 && imm < xlen.bits() as i64 => {
+                let funct2 = if op == AluOp::Srl { 0b00 } else { 0b01 };
+                0b100 << 13 | scatter(imm, CI) | funct2 << 10 | prime(rd)? << 7 | 0b01
+            }
+            AluOp::And if fits(imm, 6) => {
+                0b100 << 13 | scatter(imm, CI) | 0b10 << 10 | prime(rd)? << 7 | 0b01
+            }
+            _ => return None,
+        },
+        Inst::Op {
+            op: AluOp::Add,
+            rd,
+            rs1,
+            rs2,
+            word: false,
+        } if rd != Register::ZERO && rs2 != Register::ZERO => {
+            if rs1 == Register::ZERO {
+                0b1000 << 12 | full(rd) | low(rs2) | 0b10
+            } else if rd == rs1 {
+                0b1001 << 12 | full(rd) | low(rs2) | 0b10
+            } else {
+                return None;
+            }
+        }
+        Inst::Op {
+            op,
+            rd,
+            rs1,
+            rs2,
+            word,
+        } if rd == rs1 && (rv64 || !word) => {
+            let funct = match (op, word) {
+                (AluOp::Sub, false) => 0b0_00,
+                (AluOp::Xor, false) => 0b0_01,
+                (AluOp::Or, false) => 0b0_10,
+                (AluOp::And, false) => 0b0_11,
+                (AluOp::Sub, true) => 0b1_00,
+                (AluOp::Add, true) => 0b1_01,
+                _ => return None,
+            };
+            0b100 << 13
+                | 0b011 << 10
+                | (funct >> 2) << 12
+                | prime(rd)? << 7
+                | (funct & 0b11) << 5
+                | prime(rs2)? << 2
+                | 0b01
+        }
+        _ => return None,
+    })
+}
+
+/// Expand a 16-bit instruction into the 32-bit instruction that it stands for, or return None
+/// if it is reserved or is not an integer instruction for the register width
+pub fn expand(half: u16, xlen: Xlen) -> Option<Inst> {
+    let rv64 = xlen == Xlen::Rv64;
+    let funct3 = half >> 13;
+    let rd = Register((half >> 7 & 0x1f) as u8);
+    let rs2 = Register((half >> 2 & 0x1f) as u8);
+    let rd_prime = unprime(half >> 7);
+    let rs2_prime = unprime(half >> 2);
+    let ci_imm = sign_extend(gather(half, CI), 6);
+    let shamt = gather(half, CI);
+    let add_immediate = |rd: Register, rs1: Register, imm: i64| Inst::OpImm {
+        op: AluOp::Add,
+        rd,
+        rs1,
+        imm,
+        word: false,
+    };
+
+    Some(match (half & 0b11, funct3) {
+        (0b00, 0b000) => {
+            let imm = gather(half, CIW);
+            if imm == 0 {
+                return None;
+            }
+            add_immediate(rs2_prime, Register::SP, imm)
+        }
+        (0b00, 0b010) | (0b00, 0b011) => Inst::Load {
+            width: if funct3 == 0b010 {
+                Width::Word
+            } else if rv64 {
+                Width::Double
+            } else {
+                return None;
+            },
+            rd: rs2_prime,
+            rs1: rd_prime,
+            offset: gather(half, if funct3 == 0b010 { CL_WORD } else { CL_DOUBLE }),
+        },
+        (0b00, 0b110) | (0b00, 0b111) => Inst::Store {
+            width: if funct3 == 0b110 {
+                Width::Word
+            } else if rv64 {
+                Width::Double
+            } else {
+                return None;
+            },
+            rs1: rd_prime,
+            rs2: rs2_prime,
+            offset: gather(half, if funct3 == 0b110 { CL_WORD } else { CL_DOUBLE }),
+        },
+        (0b01, 0b000) => add_immediate(rd, rd, ci_imm),
+        (0b01, 0b001) if rv64 => {
+            if rd == Register::ZERO {
+                return None;
+            }
+            Inst::OpImm {
+                op: AluOp::Add,
+                rd,
+                rs1: rd,
+                imm: ci_imm,
+                word: true,
+            }
+        }
+        (0b01, 0b001) | (0b01, 0b101) => Inst::Jal {
+            rd: if funct3 == 0b001 {
+                Register::RA
+            } else {
+                Register::ZERO
+            },
+            offset: sign_extend(gather(half, CJ), 12),
+        },
+        (0b01, 0b010) => add_immediate(rd, Register::ZERO, ci_imm),
+        (0b01, 0b011) if rd == Register::SP => {
+            let imm = sign_extend(gather(half, CI_ADDI16SP), 10);
+            if imm == 0 {
+                return None;
+            }
+            add_immediate(Register::SP, Register::SP, imm)
+        }
+        (0b01, 0b011) => {
+            if ci_imm == 0 {
+                return None;
+            }
+            Inst::Lui { rd, imm: ci_imm }
+        }
+        (0b01, 0b100) => {
+            let (funct2, rd) = (half >> 10 & 0b11, rd_prime);
+            let immediate = |op, imm| Inst::OpImm {
+                op,
+                rd,
+                rs1: rd,
+                imm,
+                word: false,
+            };
+            match funct2 {
+                // Shifts by 32 or more only exist on RV64
+                0b00 | 0b01 if !rv64 && shamt >= 32 => return None,
+                0b00 => immediate(AluOp::Srl, shamt),
+                0b01 => immediate(AluOp::Sra, shamt),
+                0b10 => immediate(AluOp::And, ci_imm),
+                _ => {
+                    let word = half >> 12 & 1 == 1;
+                    let op = match (word, half >> 5 & 0b11) {
+                        (false, 0b00) => AluOp::Sub,
+                        (false, 0b01) => AluOp::Xor,
+                        (false, 0b10) => AluOp::Or,
+                        (false, _) => AluOp::And,
+                        (true, 0b00) if rv64 => AluOp::Sub,
+                        (true, 0b01) if rv64 => AluOp::Add,
+                        _ => return None,
+                    };
+                    Inst::Op {
+                        op,
+                        rd,
+                        rs1: rd,
+                        rs2: rs2_prime,
+                        word,
+                    }
+                }
+            }
+        }
+        (0b01, _) => Inst::Branch {
+            op: if funct3 == 0b110 {
+                BranchOp::Beq
+            } else {
+                BranchOp::Bne
+            },
+            rs1: rd_prime,
+            rs2: Register::ZERO,
+            offset: sign_extend(gather(half, CB), 9),
+        },
+        (0b10, 0b000) => {
+            if !rv64 && shamt >= 32 {
+                return None;
+            }
+            Inst::OpImm {
+                op: AluOp::Sll,
+                rd,
+                rs1: rd,
+                imm: shamt,
+                word: false,
+            }
+        }
+        (0b10, 0b010) | (0b10, 0b011) if rd != Register::ZERO => Inst::Load {
+            width: if funct3 == 0b010 {
+                Width::Word
+            } else if rv64 {
+                Width::Double
+            } else {
+                return None;
+            },
+            rd,
+            rs1: Register::SP,
+            offset: gather(half, if funct3 == 0b010 { CI_LWSP } else { CI_LDSP }),
+        },
+        (0b10, 0b100) => {
+            let link = half >> 12 & 1 == 1;
+            match (link, rd, rs2) {
+                (_, Register::ZERO, Register::ZERO) => return None,
+                (_, rs1, Register::ZERO) => Inst::Jalr {
+                    rd: if link { Register::RA } else { Register::ZERO },
+                    rs1,
+                    offset: 0,
+                },
+                (false, rd, rs2) => Inst::Op {
+                    op: AluOp::Add,
+                    rd,
+                    rs1: Register::ZERO,
+                    rs2,
+                    word: false,
+                },
+                (true, rd, rs2) => Inst::Op {
+                    op: AluOp::Add,
+                    rd,
+                    rs1: rd,
+                    rs2,
+                    word: false,
+                },
+            }
+        }
+        (0b10, 0b110) | (0b10, 0b111) => Inst::Store {
+            width: if funct3 == 0b110 {
+                Width::Word
+            } else if rv64 {
+                Width::Double
+            } else {
+                return None;
+            },
+            rs1: Register::SP,
+            rs2,
+            offset: gather(half, if funct3 == 0b110 { CSS_SWSP } else { CSS_SDSP }),
+        },
+        _ => return None,
+    })
+}
@@ -0,0 +1,148 @@
+use super::{encoder::alu_function, sign_extend, AluOp, BranchOp, Inst, Register, Width, Xlen};
+
+/// The operations that can be encoded in register-register instructions, in no particular order
+const ALU_OPS: [AluOp; 18] = [
+    AluOp::Add,
+    AluOp::Sub,
+    AluOp::Sll,
+    AluOp::Slt,
+    AluOp::Sltu,
+    AluOp::Xor,
+    AluOp::Srl,
+    AluOp::Sra,
+    AluOp::Or,
+    AluOp::And,
+    AluOp::Mul,
+    AluOp::Mulh,
+    AluOp::Mulhsu,
+    AluOp::Mulhu,
+    AluOp::Div,
+    AluOp::Divu,
+    AluOp::Rem,
+    AluOp::Remu,
+];
+
+/// Decode a 32-bit instruction word, or return None if it is not an instruction that can be
+/// encoded for the register width
+pub fn decode(word: u32, xlen: Xlen) -> Option<Inst> {
+    let opcode = word & 0x7f;
+    let rd = Register((word >> 7 & 0x1f) as u8);
+    let funct3 = word >> 12 & 0x7;
+    let rs1 = Register((word >> 15 & 0x1f) as u8);
+    let rs2 = Register((word >> 20 & 0x1f) as u8);
+    let funct7 = word >> 25;
+
+    // The immediates of each format, sign-extended
+    let i_imm = (word as i32 >> 20) as i64;
+    let s_imm = ((word as i32 >> 20) & !0x1f) as i64 | (word >> 7 & 0x1f) as i64;
+    let b_imm = sign_extend(
+        ((word >> 31) << 12
+            | (word >> 7 & 1) << 11
+            | (word >> 25 & 0x3f) << 5
+            | (word >> 8 & 0xf) << 1) as i64,
+        13,
+    );
+    let u_imm = sign_extend((word >> 12) as i64, 20);
+    let j_imm = sign_extend(
+        ((word >> 31) << 20
+            | (word >> 12 & 0xff) << 12
+            | (word >> 20 & 1) << 11
+            | (word >> 21 & 0x3ff) << 1) as i64,
+        21,
+    );
+
+    let inst = match opcode {
+        0b011_0111 => Inst::Lui { rd, imm: u_imm },
+        0b001_0111 => Inst::Auipc { rd, imm: u_imm },
+        0b110_1111 => Inst::Jal { rd, offset: j_imm },
+        0b110_0111 if funct3 == 0 => Inst::Jalr {
+            rd,
+            rs1,
+            offset: i_imm,
+        },
+        0b110_0011 => Inst::Branch {
+            op: match funct3 {
+                0 => BranchOp::Beq,
+                1 => BranchOp::Bne,
+                4 => BranchOp::Blt,
+                5 => BranchOp::Bge,
+                6 => BranchOp::Bltu,
+                7 => BranchOp::Bgeu,
+                _ => return None,
+            },
+            rs1,
+            rs2,
+            offset: b_imm,
+        },
+        0b000_0011 => Inst::Load {
+            width: match funct3 {
+                0 => Width::Byte,
+                1 => Width::Half,
+                2 => Width::Word,
+                3 => Width::Double,
+                4 => Width::ByteUnsigned,
+                5 => Width::HalfUnsigned,
+                6 => Width::WordUnsigned,
+                _ => return None,
+            },
+            rd,
+            rs1,
+            offset: i_imm,
+        },
+        0b010_0011 => Inst::Store {
+            width: match funct3 {
+                0 => Width::Byte,
+                1 => Width::Half,
+                2 => Width::Word,
+                3 => Width::Double,
+                _ => return None,
+            },
+            rs1,
+            rs2,
+            offset: s_imm,
+        },
+        0b001_0011 | 0b001_1011 => {
+            let word_form = opcode == 0b001_1011;
+            let op = match funct3 {
+                0 => AluOp::Add,
+                1 => AluOp::Sll,
+                2 => AluOp::Slt,
+                3 => AluOp::Sltu,
+                4 => AluOp::Xor,
+                5 if word >> 30 & 1 == 1 => AluOp::Sra,
+                5 => AluOp::Srl,
+                6 => AluOp::Or,
+                _ => AluOp::And,
+            };
+            let imm = match op {
+                // Shifts by up to 63 on RV64 use the lowest bit of funct7 for the amount
+                AluOp::Sll | AluOp::Srl | AluOp::Sra => (word >> 20 & 0x3f) as i64,
+                _ => i_imm,
+            };
+            Inst::OpImm {
+                op,
+                rd,
+                rs1,
+                imm,
+                word: word_form,
+            }
+        }
+        0b011_0011 | 0b011_1011 => Inst::Op {
+            op: *ALU_OPS
+                .iter()
+                .find(|op| alu_function(**op) == (funct7, funct3))?,
+            rd,
+            rs1,
+            rs2,
+            word: opcode == 0b011_1011,
+        },
+        0b111_0011 if word == 0b111_0011 => Inst::Ecall,
+        _ => return None,
+    };
+
+    // Reject the encodings that are reserved, rather than decoding them to something different
+    super::encode(inst, xlen)
+        .ok()
+        .filter(|encoded| *encoded == word)
+        .map(|_| inst)
+}
@@ -0,0 +1,355 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use super::{split_offset, AluOp, BranchOp, Inst, Register, Width, Xlen, INSTRUCTION_LENGTH};
+
+/// Major opcodes, in the lowest 7 bits of every instruction
+const OP_LUI: u32 = 0b011_0111;
+const OP_AUIPC: u32 = 0b001_0111;
+const OP_JAL: u32 = 0b110_1111;
+const OP_JALR: u32 = 0b110_0111;
+const OP_BRANCH: u32 = 0b110_0011;
+const OP_LOAD: u32 = 0b000_0011;
+const OP_STORE: u32 = 0b010_0011;
+const OP_IMM: u32 = 0b001_0011;
+const OP_IMM_32: u32 = 0b001_1011;
+const OP: u32 = 0b011_0011;
+const OP_32: u32 = 0b011_1011;
+const OP_SYSTEM: u32 = 0b111_0011;
+
+/// The error returned when an instruction cannot be encoded or a label cannot be resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The instruction does not exist, or does not exist for the register width
+    Unsupported(Inst),
+    /// An immediate or offset does not fit in the bits available for it
+    OutOfRange {
+        inst: Inst,
+        value: i64,
+    },
+    /// A branch or jump offset is not a multiple of 2
+    Misaligned {
+        inst: Inst,
+        offset: i64,
+    },
+    /// A branch to a label that is too far away
+    BranchOutOfRange(String),
+    /// A jump to a label that is too far away
+    JumpOutOfRange(String),
+    UndefinedLabel(String),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Unsupported(inst) => write!(f, "`{:?}` cannot be encoded", inst),
+            EncodeError::OutOfRange { inst, value } => {
+                write!(f, "immediate {} is out of range in `{:?}`", value, inst)
+            }
+            EncodeError::Misaligned { inst, offset } => {
+                write!(
+                    f,
+                    "offset {} is not a multiple of 2 in `{:?}`",
+                    offset, inst
+                )
+            }
+            EncodeError::BranchOutOfRange(label) => {
+                write!(f, "branch to `{}` is out of range", label)
+            }
+            EncodeError::JumpOutOfRange(label) => write!(f, "jump to `{}` is out of range", label),
+            EncodeError::UndefinedLabel(label) => write!(f, "undefined label `{}`", label),
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+/// Encode an instruction as a 32-bit instruction word
+pub fn encode(inst: Inst, xlen: Xlen) -> Result<u32, EncodeError> {
+    let out_of_range = |value: i64| EncodeError::OutOfRange { inst, value };
+    let check = |value: i64, bits: u32| {
+        let limit = 1 << (bits - 1);
+        if (-limit..limit).contains(&value) {
+            Ok(value as u32)
+        } else {
+            Err(out_of_range(value))
+        }
+    };
+    let check_offset = |offset: i64, bits: u32| {
+        if offset % 2 != 0 {
+            return Err(EncodeError::Misaligned { inst, offset });
+        }
+        check(offset, bits)
+    };
+    let word_op = |word: bool| {
+        if word && xlen == Xlen::Rv32 {
+            Err(EncodeError::Unsupported(inst))
+        } else {
+            Ok(word)
+        }
+    };
+
+    Ok(match inst {
+        Inst::Lui { rd, imm } => u_type(OP_LUI, rd, check(imm, 20)?),
+        Inst::Auipc { rd, imm } => u_type(OP_AUIPC, rd, check(imm, 20)?),
+        Inst::Jal { rd, offset } => {
+            let imm = check_offset(offset, 21)?;
+            let bits = (imm & 0x10_0000) << 11
+                | (imm & 0x7fe) << 20
+                | (imm & 0x800) << 9
+                | (imm & 0xf_f000);
+            bits | register(rd) << 7 | OP_JAL
+        }
+        Inst::Jalr { rd, rs1, offset } => i_type(OP_JALR, 0, rd, rs1, check(offset, 12)?),
+        Inst::Branch {
+            op,
+            rs1,
+            rs2,
+            offset,
+        } => {
+            let imm = check_offset(offset, 13)?;
+            let funct3 = match op {
+                BranchOp::Beq => 0,
+                BranchOp::Bne => 1,
+                BranchOp::Blt => 4,
+                BranchOp::Bge => 5,
+                BranchOp::Bltu => 6,
+                BranchOp::Bgeu => 7,
+            };
+            let bits =
+                (imm & 0x1000) << 19 | (imm & 0x7e0) << 20 | (imm & 0x1e) << 7 | (imm & 0x800) >> 4;
+            bits | register(rs2) << 20 | register(rs1) << 15 | funct3 << 12 | OP_BRANCH
+        }
+        Inst::Load {
+            width,
+            rd,
+            rs1,
+            offset,
+        } => {
+            let funct3 = match width {
+                Width::Byte => 0,
+                Width::Half => 1,
+                Width::Word => 2,
+                Width::Double => 3,
+                Width::ByteUnsigned => 4,
+                Width::HalfUnsigned => 5,
+                Width::WordUnsigned => 6,
+            };
+            if xlen == Xlen::Rv32 && matches!(width, Width::Double | Width::WordUnsigned) {
+                return Err(EncodeError::Unsupported(inst));
+            }
+            i_type(OP_LOAD, funct3, rd, rs1, check(offset, 12)?)
+        }
+        Inst::Store {
+            width,
+            rs1,
+            rs2,
+            offset,
+        } => {
+            let funct3 = match width {
+                Width::Byte => 0,
+                Width::Half => 1,
+                Width::Word => 2,
+                Width::Double if xlen == Xlen::Rv64 => 3,
+                _ => return Err(EncodeError::Unsupported(inst)),
+            };
+            let imm = check(offset, 12)?;
+            (imm & 0xfe0) << 20
+                | register(rs2) << 20
+                | register(rs1) << 15
+                | funct3 << 12
+                | (imm & 0x1f) << 7
+                | OP_STORE
+        }
+        Inst::OpImm {
+            op,
+            rd,
+            rs1,
+            imm,
+            word,
+        } => {
+            let opcode = if word_op(word)? { OP_IMM_32 } else { OP_IMM };
+            let (funct7, funct3) = alu_function(op);
+            match op {
+                AluOp::Sll | AluOp::Srl | AluOp::Sra => {
+                    let bits = if word { 32 } else { xlen.bits() as i64 };
+                    if !(0..bits).contains(&imm) {
+                        return Err(out_of_range(imm));
+                    }
+                    i_type(opcode, funct3, rd, rs1, funct7 << 5 | imm as u32)
+                }
+                AluOp::Add => i_type(opcode, funct3, rd, rs1, check(imm, 12)?),
+                AluOp::Slt | AluOp::Sltu | AluOp::Xor | AluOp::Or | AluOp::And if !word => {
+                    i_type(opcode, funct3, rd, rs1, check(imm, 12)?)
+                }
+                _ => return Err(EncodeError::Unsupported(inst)),
+            }
+        }
+        Inst::Op {
+            op,
+            rd,
+            rs1,
+            rs2,
+            word,
+        } => {
+            let opcode = if word_op(word)? { OP_32 } else { OP };
+            let (funct7, funct3) = alu_function(op);
+            let word_form = matches!(
+                op,
+                AluOp::Add
+                    | AluOp::Sub
+                    | AluOp::Sll
+                    | AluOp::Srl
+                    | AluOp::Sra
+                    | AluOp::Mul
+                    | AluOp::Div
+                    | AluOp::Divu
+                    | AluOp::Rem
+                    | AluOp::Remu
+            );
+            if word && !word_form {
+                return Err(EncodeError::Unsupported(inst));
+            }
+            funct7 << 25
+                | register(rs2) << 20
+                | register(rs1) << 15
+                | funct3 << 12
+                | register(rd) << 7
+                | opcode
+        }
+        Inst::Ecall => OP_SYSTEM,
+    })
+}
+
+/// The `funct7` and `funct3` fields that select an arithmetic operation
+pub(super) fn alu_function(op: AluOp) -> (u32, u32) {
+    match op {
+        AluOp::Add => (0, 0),
+        AluOp::Sub => (0b010_0000, 0),
+        AluOp::Sll => (0, 1),
+        AluOp::Slt => (0, 2),
+        AluOp::Sltu => (0, 3),
+        AluOp::Xor => (0, 4),
+        AluOp::Srl => (0, 5),
+        AluOp::Sra => (0b010_0000, 5),
+        AluOp::Or => (0, 6),
+        AluOp::And => (0, 7),
+        AluOp::Mul => (1, 0),
+        AluOp::Mulh => (1, 1),
+        AluOp::Mulhsu => (1, 2),
+        AluOp::Mulhu => (1, 3),
+        AluOp::Div => (1, 4),
+        AluOp::Divu => (1, 5),
+        AluOp::Rem => (1, 6),
+        AluOp::Remu => (1, 7),
+    }
+}
+
+fn register(register: Register) -> u32 {
+    register.0 as u32 & 0x1f
+}
+
+/// An instruction with a 12-bit immediate in its upper bits
+fn i_type(opcode: u32, funct3: u32, rd: Register, rs1: Register, imm: u32) -> u32 {
+    (imm & 0xfff) << 20 | register(rs1) << 15 | funct3 << 12 | register(rd) << 7 | opcode
+}
+
+/// An instruction with a 20-bit upper immediate
+fn u_type(opcode: u32, rd: Register, imm: u32) -> u32 {
+    (imm & 0xf_ffff) << 12 | register(rd) << 7 | opcode
+}
+
+/// An instruction that may refer to a label, which is resolved once every label's address is
+/// known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pending {
+    Ready(Inst),
+    Branch {
+        op: BranchOp,
+        rs1: Register,
+        rs2: Register,
+        target: String,
+    },
+    Jal {
+        rd: Register,
+        target: String,
+    },
+    /// The `auipc` of an `auipc`/`addi` or `auipc`/`jalr` pair addressing `target`
+    PcRelHigh {
+        rd: Register,
+        target: String,
+    },
+    /// The second instruction of a pair, which immediately follows its `auipc`
+    PcRelLow {
+        jump: bool,
+        rd: Register,
+        rs1: Register,
+        target: String,
+    },
+}
+
+impl Pending {
+    /// Resolve the label in an instruction at address `pc`
+    pub fn resolve(&self, pc: u64, symbols: &HashMap<String, u64>) -> Result<Inst, EncodeError> {
+        let offset_to = |target: &str, auipc: u64| -> Result<i64, EncodeError> {
+            symbols
+                .get(target)
+                .map(|address| address.wrapping_sub(auipc) as i64)
+                .ok_or_else(|| EncodeError::UndefinedLabel(target.to_string()))
+        };
+
+        Ok(match self {
+            Pending::Ready(inst) => *inst,
+            Pending::Branch {
+                op,
+                rs1,
+                rs2,
+                target,
+            } => {
+                let offset = offset_to(target, pc)?;
+                if !(-4096..4096).contains(&offset) {
+                    return Err(EncodeError::BranchOutOfRange(target.clone()));
+                }
+                Inst::Branch {
+                    op: *op,
+                    rs1: *rs1,
+                    rs2: *rs2,
+                    offset,
+                }
+            }
+            Pending::Jal { rd, target } => {
+                let offset = offset_to(target, pc)?;
+                if !(-(1 << 20)..(1 << 20)).contains(&offset) {
+                    return Err(EncodeError::JumpOutOfRange(target.clone()));
+                }
+                Inst::Jal { rd: *rd, offset }
+            }
+            Pending::PcRelHigh { rd, target } => {
+                let (high, _) = split_offset(offset_to(target, pc)?);
+                Inst::Auipc { rd: *rd, imm: high }
+            }
+            Pending::PcRelLow {
+                jump,
+                rd,
+                rs1,
+                target,
+            } => {
+                let (_, low) = split_offset(offset_to(target, pc - INSTRUCTION_LENGTH)?);
+                if *jump {
+                    Inst::Jalr {
+                        rd: *rd,
+                        rs1: *rs1,
+                        offset: low,
+                    }
+                } else {
+                    Inst::OpImm {
+                        op: AluOp::Add,
+                        rd: *rd,
+                        rs1: *rs1,
+                        imm: low,
+                        word: false,
+                    }
+                }
+            }
+        })
+    }
+}
@@ -1,3 +1,12 @@
 pub mod compiler;
+pub mod diagnostics;
+pub mod dialect;
+pub mod difftest;
+pub mod elf;
+pub mod generator;
 pub mod instruction;
+pub mod interpreter;
+pub mod isa;
+pub mod optimizer;
 pub mod parser;
+pub mod riscv_sim;
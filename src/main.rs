@@ -1,34 +1,534 @@
-use std::{env, fs, fs::File, io::Read};
+use std::{
+    env, fs,
+    io::{self, IsTerminal},
+    path::Path,
+    process,
+};
 
-use brainfuck_riscv::{compiler::compile_risc_v, parser::parse};
+use brainfuck_riscv::{
+    compiler::{
+        self,
+        riscv::{OVERFLOW_EXIT_CODE, UNDERFLOW_EXIT_CODE},
+        Backend,
+    },
+    diagnostics::{self, Diagnostic, Renderer},
+    dialect::{CellWidth, Dialect, EofBehavior},
+    difftest::{self, Harness},
+    elf::{self, FloatAbi},
+    instruction::Instruction,
+    interpreter::{InterpretError, Interpreter},
+    isa::Arch,
+    optimizer::{OptLevel, PassManager},
+    parser::parse,
+    riscv_sim::{self, Machine, Program, Xlen},
+};
 
 /// The message shown to the user when they type a command incorrectly
-const USAGE_MESSAGE: &str = "Usage: bf <input> [-o <output>]";
+const USAGE_MESSAGE: &str = "Usage: bf [run [--interp] | difftest] <input> [-o <output>] [-O0|-O1|-O2] \
+[--emit <asm|elf|obj>] [--float-abi <soft|single|double>] [--enable-pass <pass>] [--disable-pass <pass>] [--target <target>] \
+[--target-option <name>=<value>] [--runtime <rars|linux|library>] [--arch <rv32i|rv32im|rv64i|rv64gc>] [--compressed] [--cache-cell] [--buffered-io] [--bounds-check] [--tape-size <cells>] \
+[--cell-width <8|16|32|64>] [--tape-start <cell|middle>] [--eof <unchanged|zero|minus-one>] [--color <auto|always|never>]";
 
-/// The default filename of the output file
+/// The default filenames of the output file for each kind of output
 const DEFAULT_OUTPUT_FILENAME: &str = "out.asm";
+const DEFAULT_EXECUTABLE_FILENAME: &str = "a.out";
+const DEFAULT_OBJECT_FILENAME: &str = "out.o";
+
+/// The exit status used when the program cannot be compiled
+const EXIT_FAILURE: i32 = 1;
+
+/// The exit status used when the command line is invalid
+const EXIT_USAGE: i32 = 2;
+
+/// What to do with the compiled program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Write the generated assembly to the output file
+    Compile,
+    /// Run the generated assembly, or an assembly input file, in the built-in emulator
+    Run,
+    /// Run the optimized program in the interpreter
+    Interpret,
+    /// Check that the interpreter, optimizer, and backend agree on every program in a directory
+    Difftest,
+}
+
+/// The kind of file that the compile command writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    /// RISC-V assembly
+    Assembly,
+    /// A static ELF executable for riscv64 Linux
+    Elf,
+    /// A relocatable ELF object file that defines `bf_main`
+    Object,
+}
+
+/// Options given on the command line
+struct Options {
+    command: Command,
+    emit: Emit,
+    float_abi: FloatAbi,
+    /// Whether compressed instructions were requested, so that the savings are reported
+    compressed: bool,
+    input_filename: String,
+    output_filename: String,
+    color: bool,
+    passes: PassManager,
+    dialect: Dialect,
+    backend: Box<dyn Backend>,
+}
+
+/// Parses the command line arguments, not including the program name
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let (mut command, args) = match args.first().map(String::as_str) {
+        Some("run") => (Command::Run, &args[1..]),
+        Some("difftest") => (Command::Difftest, &args[1..]),
+        _ => (Command::Compile, args),
+    };
+
+    let mut input_filename = None;
+    let mut output_filename = None;
+    let mut emit = Emit::Assembly;
+    let mut float_abi = None;
+    let mut color = None;
+    let mut target = None;
+    let mut arch = None;
+    let mut compressed = false;
+    let mut level = OptLevel::O2;
+    let mut dialect = Dialect::default();
+    let mut tape_start = None;
+
+    // Passes are enabled or disabled after the optimization level is known, in the given order
+    let mut pass_overrides: Vec<(&str, bool)> = Vec::new();
+
+    // Target options are set after the target is known, in the given order
+    let mut target_options: Vec<(&str, &str)> = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let value = args.next().ok_or("missing value for `-o`")?;
+                output_filename = Some(value.clone());
+            }
+            "--color" => {
+                let value = args.next().ok_or("missing value for `--color`")?;
+                color = match value.as_str() {
+                    "auto" => None,
+                    "always" => Some(true),
+                    "never" => Some(false),
+                    _ => return Err(format!("invalid value for `--color`: `{}`", value)),
+                };
+            }
+            "--interp" if command == Command::Run => command = Command::Interpret,
+            "--emit" => {
+                let value = args.next().ok_or("missing value for `--emit`")?;
+                emit = match value.as_str() {
+                    "asm" => Emit::Assembly,
+                    "elf" => Emit::Elf,
+                    "obj" => Emit::Object,
+                    _ => return Err(format!("invalid value for `--emit`: `{}`", value)),
+                };
+            }
+            "--float-abi" => {
+                let value = args.next().ok_or("missing value for `--float-abi`")?;
+                float_abi = Some(
+                    FloatAbi::from_name(value)
+                        .ok_or_else(|| format!("invalid value for `--float-abi`: `{}`", value))?,
+                );
+            }
+            "-O0" => level = OptLevel::O0,
+            "-O1" => level = OptLevel::O1,
+            "-O2" => level = OptLevel::O2,
+            "--enable-pass" => {
+                let value = args.next().ok_or("missing value for `--enable-pass`")?;
+                pass_overrides.push((value, true));
+            }
+            "--disable-pass" => {
+                let value = args.next().ok_or("missing value for `--disable-pass`")?;
+                pass_overrides.push((value, false));
+            }
+            "--target" => {
+                target = Some(args.next().ok_or("missing value for `--target`")?.as_str());
+            }
+            "--target-option" => {
+                let value = args.next().ok_or("missing value for `--target-option`")?;
+                let option = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid value for `--target-option`: `{}`", value))?;
+                target_options.push(option);
+            }
+            "--arch" => {
+                let value = args.next().ok_or("missing value for `--arch`")?;
+                arch = Some(
+                    Arch::from_name(value)
+                        .ok_or_else(|| format!("invalid value for `--arch`: `{}`", value))?,
+                );
+                target_options.push(("arch", value));
+            }
+            "--runtime" => {
+                let value = args.next().ok_or("missing value for `--runtime`")?;
+                target_options.push(("runtime", value));
+            }
+            "--compressed" => {
+                compressed = true;
+                target_options.push(("compressed", "true"));
+            }
+            "--cache-cell" => target_options.push(("cache-cell", "true")),
+            "--buffered-io" => target_options.push(("buffered-io", "true")),
+            "--bounds-check" => target_options.push(("bounds-check", "true")),
+            "--tape-size" => {
+                let value = args.next().ok_or("missing value for `--tape-size`")?;
+                dialect.tape_length = value
+                    .parse()
+                    .ok()
+                    .filter(|length| *length > 0)
+                    .ok_or_else(|| format!("invalid value for `--tape-size`: `{}`", value))?;
+            }
+            "--cell-width" => {
+                let value = args.next().ok_or("missing value for `--cell-width`")?;
+                dialect.cell_width = value
+                    .parse()
+                    .ok()
+                    .and_then(CellWidth::from_bits)
+                    .ok_or_else(|| format!("invalid value for `--cell-width`: `{}`", value))?;
+            }
+            "--eof" => {
+                let value = args.next().ok_or("missing value for `--eof`")?;
+                dialect.eof = EofBehavior::from_name(value)
+                    .ok_or_else(|| format!("invalid value for `--eof`: `{}`", value))?;
+            }
+            "--tape-start" => {
+                tape_start = Some(args.next().ok_or("missing value for `--tape-start`")?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ if input_filename.is_none() => input_filename = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+
+    // By default, only use color when writing to a terminal and NO_COLOR is not set
+    let color =
+        color.unwrap_or_else(|| io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none());
+
+    let mut passes = PassManager::new(level);
+    for (name, enabled) in pass_overrides {
+        let result = if enabled {
+            passes.enable(name)
+        } else {
+            passes.disable(name)
+        };
+        result.map_err(|error| error.to_string())?;
+    }
+
+    // The start of the tape depends on its length, so it is only known once all options are
+    if let Some(value) = tape_start {
+        dialect.tape_start = match value.as_str() {
+            "middle" => dialect.tape_length / 2,
+            _ => value
+                .parse()
+                .ok()
+                .filter(|start| *start < dialect.tape_length)
+                .ok_or_else(|| format!("invalid value for `--tape-start`: `{}`", value))?,
+        };
+    }
+
+    // Executables are for riscv64 Linux, so they use that target and runtime unless told otherwise.
+    // Otherwise, the ISA decides the target.
+    let target = match (target, arch, emit) {
+        (Some(target), _, _) => target,
+        (None, Some(arch), _) if arch.xlen == Xlen::Rv32 => "riscv32",
+        (None, Some(_), _) | (None, None, Emit::Elf) => "riscv64",
+        (None, None, _) => compiler::TARGETS[0],
+    };
+    if emit == Emit::Elf {
+        if target != "riscv64" {
+            return Err("`--emit elf` requires the riscv64 target".to_string());
+        }
+        if target_options
+            .iter()
+            .any(|(name, value)| *name == "runtime" && *value != "linux")
+        {
+            return Err("`--emit elf` requires the linux runtime".to_string());
+        }
+        target_options.insert(0, ("runtime", "linux"));
+    }
+    // Object files define a function to be called, so they always use the library runtime
+    if emit == Emit::Object {
+        if target_options
+            .iter()
+            .any(|(name, value)| *name == "runtime" && *value != "library")
+        {
+            return Err("`--emit obj` requires the library runtime".to_string());
+        }
+        target_options.insert(0, ("runtime", "library"));
+    }
+    let library = target_options
+        .iter()
+        .rev()
+        .find(|(name, _)| *name == "runtime")
+        .is_some_and(|(_, value)| *value == "library");
+    if library && matches!(command, Command::Run | Command::Difftest) {
+        return Err("the library runtime cannot be run without a caller".to_string());
+    }
+
+    let mut backend = compiler::backend(target, dialect).ok_or_else(|| {
+        format!(
+            "unknown target `{}`, expected one of: {}",
+            target,
+            compiler::TARGETS.join(", ")
+        )
+    })?;
+    for (name, value) in target_options {
+        backend
+            .set_option(name, value)
+            .map_err(|error| error.to_string())?;
+    }
+    // The interpreter supports every dialect, whatever the target
+    if command != Command::Interpret {
+        backend.validate().map_err(|error| error.to_string())?;
+    }
+
+    let xlen = Xlen::for_target(target).unwrap_or(Xlen::Rv32);
+    let arch = arch.unwrap_or_else(|| Arch::default_for(xlen));
+    Ok(Options {
+        command,
+        emit,
+        float_abi: float_abi.unwrap_or_else(|| FloatAbi::default_for(arch)),
+        compressed,
+        input_filename: input_filename.ok_or("missing input file")?,
+        output_filename: output_filename.unwrap_or_else(|| match emit {
+            Emit::Assembly => DEFAULT_OUTPUT_FILENAME.to_string(),
+            Emit::Elf => DEFAULT_EXECUTABLE_FILENAME.to_string(),
+            Emit::Object => DEFAULT_OBJECT_FILENAME.to_string(),
+        }),
+        color,
+        passes,
+        dialect,
+        backend,
+    })
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let input_filename = args.get(1).expect(USAGE_MESSAGE);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            let renderer = Renderer::new(io::stderr().is_terminal());
+            eprint!("{}", renderer.render(&Diagnostic::error(message), "", ""));
+            eprintln!("{}", USAGE_MESSAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
 
-    // Get the output filename, if provided. Otherwise, use the default.
-    let output_index = args.iter().position(|s| s == "-o" || s == "--output");
-    let output_filename = if let Some(index) = output_index {
-        args.get(index + 1).expect(USAGE_MESSAGE)
-    } else {
-        DEFAULT_OUTPUT_FILENAME
+    let renderer = Renderer::new(options.color);
+    let fail = |diagnostic: Diagnostic| -> ! {
+        eprint!("{}", renderer.render(&diagnostic, "", ""));
+        process::exit(EXIT_FAILURE);
     };
 
+    // The input of the difftest command is a directory of programs
+    if options.command == Command::Difftest {
+        run_difftest(options);
+    }
+
     // Read the source file
-    let mut source = String::new();
-    let mut input_file = File::open(input_filename).expect("Failed to open source file");
-    input_file
-        .read_to_string(&mut source)
-        .expect("Failed to read source file");
-
-    // Compile and write to output
-    let program = parse(&source);
-    let output = compile_risc_v(&program);
-    fs::write(output_filename, output).expect("Failed to write output");
+    let source = fs::read_to_string(&options.input_filename).unwrap_or_else(|error| {
+        fail(Diagnostic::error(format!(
+            "failed to read `{}`: {}",
+            options.input_filename, error
+        )))
+    });
+
+    // Assembly files are run as they are, without compiling
+    let is_assembly = [".s", ".asm"]
+        .iter()
+        .any(|extension| options.input_filename.ends_with(extension));
+    match options.command {
+        Command::Run if is_assembly => run(&options, &source),
+        Command::Interpret if is_assembly => fail(Diagnostic::error(
+            "`--interp` cannot run an assembly file".to_string(),
+        )),
+        _ => {}
+    }
+
+    // Report any warnings, then any errors
+    for warning in diagnostics::lint(&source) {
+        eprintln!(
+            "{}",
+            renderer.render(&warning, &options.input_filename, &source)
+        );
+    }
+
+    let program = match parse(&source) {
+        Ok(program) => program,
+        Err(error) => {
+            let errors = diagnostics::from_parse_error(&error, &source);
+            for diagnostic in &errors {
+                eprintln!(
+                    "{}",
+                    renderer.render(diagnostic, &options.input_filename, &source)
+                );
+            }
+            fail(Diagnostic::error(format!(
+                "could not compile `{}` due to {} previous error{}",
+                options.input_filename,
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" }
+            )));
+        }
+    };
+
+    // Optimize, compile, and write to output
+    let program = options.passes.run(&program);
+    if options.command == Command::Interpret {
+        interpret(&options, &program);
+    }
+    let output = compiler::compile(options.backend.as_mut(), &program);
+    if options.command == Command::Run {
+        run(&options, &output);
+    }
+
+    // Assembly is only assembled to write an ELF file, or to report the size of the code
+    let xlen = Xlen::for_target(options.backend.name()).unwrap_or(Xlen::Rv32);
+    let assembled = if options.emit != Emit::Assembly || options.compressed {
+        let program = riscv_sim::assemble(&output, xlen).unwrap_or_else(|error| {
+            fail(Diagnostic::error(format!(
+                "failed to assemble the generated code: {}",
+                error
+            )))
+        });
+        if options.compressed {
+            report_compression(&program);
+        }
+        Some(program)
+    } else {
+        None
+    };
+    let output = match (options.emit, assembled) {
+        (Emit::Elf, Some(program)) => elf::executable(&program),
+        (Emit::Object, Some(program)) => elf::relocatable(&program, options.float_abi),
+        _ => output.into_bytes(),
+    };
+    write_output(&options, &output).unwrap_or_else(|error| {
+        fail(Diagnostic::error(format!(
+            "failed to write `{}`: {}",
+            options.output_filename, error
+        )))
+    });
+}
+
+/// Print how much smaller compressed instructions made the text section. Each compressed
+/// instruction saves 2 bytes.
+fn report_compression(program: &Program) {
+    let saved = program.compressed as u64 * 2;
+    let uncompressed = program.text_size + saved;
+    eprintln!(
+        "compressed {} instructions, saving {} of {} bytes of code ({:.1}%)",
+        program.compressed,
+        saved,
+        uncompressed,
+        100.0 * saved as f64 / uncompressed.max(1) as f64
+    );
+}
+
+/// Write the output file, which is made executable if it is an executable
+fn write_output(options: &Options, output: &[u8]) -> io::Result<()> {
+    fs::write(&options.output_filename, output)?;
+
+    #[cfg(unix)]
+    if options.emit == Emit::Elf {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&options.output_filename, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Assemble and run a program in the emulator with the standard streams, then exit with its
+/// exit code
+fn run(options: &Options, assembly: &str) -> ! {
+    let xlen = Xlen::for_target(options.backend.name()).unwrap_or(Xlen::Rv32);
+
+    let result = riscv_sim::assemble(assembly, xlen).and_then(|program| {
+        Machine::new(&program).run(
+            &mut io::stdin().lock(),
+            &mut io::stdout().lock(),
+            &mut io::stderr().lock(),
+        )
+    });
+
+    match result {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            let renderer = Renderer::new(options.color);
+            eprint!(
+                "{}",
+                renderer.render(&Diagnostic::error(error.to_string()), "", "")
+            );
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Run a program in the interpreter with the standard streams, then exit. Accessing a cell off
+/// the tape exits with the same codes as a bounds checked program.
+fn interpret(options: &Options, program: &[Instruction]) -> ! {
+    let result = Interpreter::new(program, options.dialect)
+        .run(&mut io::stdin().lock(), &mut io::stdout().lock());
+
+    let code = match &result {
+        Ok(()) => process::exit(0),
+        Err(InterpretError::Underflow(_)) => UNDERFLOW_EXIT_CODE,
+        Err(InterpretError::Overflow(_)) => OVERFLOW_EXIT_CODE,
+        Err(_) => EXIT_FAILURE,
+    };
+    if let Err(error) = result {
+        let renderer = Renderer::new(options.color);
+        eprint!(
+            "{}",
+            renderer.render(&Diagnostic::error(error.to_string()), "", "")
+        );
+    }
+    process::exit(code);
+}
+
+/// Run every program in the input directory with the differential test harness, then exit
+/// with a failure status if any did not pass
+fn run_difftest(options: Options) -> ! {
+    let renderer = Renderer::new(options.color);
+    let cases = difftest::load_corpus(Path::new(&options.input_filename)).unwrap_or_else(|error| {
+        eprint!(
+            "{}",
+            renderer.render(
+                &Diagnostic::error(format!(
+                    "failed to read `{}`: {}",
+                    options.input_filename, error
+                )),
+                "",
+                ""
+            )
+        );
+        process::exit(EXIT_FAILURE);
+    });
+
+    let mut harness = Harness::new(options.dialect, options.passes, options.backend);
+    let mut failed = 0;
+    for case in &cases {
+        match harness.check(case) {
+            Ok(()) => println!("test {} ... ok", case.name),
+            Err(failure) => {
+                println!("test {} ... FAILED", case.name);
+                println!("    {}: {}", failure.runner, failure.message);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\ndifftest result: {} passed; {} failed",
+        cases.len() - failed,
+        failed
+    );
+    process::exit(if failed == 0 { 0 } else { EXIT_FAILURE });
 }
@@ -0,0 +1,461 @@
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+/// How aggressively to optimize a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// No optimization
+    O0,
+    /// Only replace simple loops
+    O1,
+    /// All optimizations
+    O2,
+}
+
+/// A transformation of a program into an equivalent program
+pub trait Pass {
+    /// The name used to enable or disable the pass from the command line
+    fn name(&self) -> &'static str;
+
+    /// The lowest optimization level at which the pass runs by default
+    fn level(&self) -> OptLevel;
+
+    /// Transform the program, which may leave the targets of its loops out of date
+    fn run(&self, program: &[Instruction]) -> Vec<Instruction>;
+}
+
+/// Returns every available pass, in the order that they run
+pub fn all_passes() -> Vec<Box<dyn Pass>> {
+    vec![
+        Box::new(ScanLoops),
+        Box::new(MultiplyLoops),
+        Box::new(ClearLoops),
+        Box::new(DeferPointerMoves),
+    ]
+}
+
+/// The error returned when enabling or disabling a pass that does not exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPass(pub String);
+
+impl fmt::Display for UnknownPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = all_passes().iter().map(|pass| pass.name()).collect();
+        write!(
+            f,
+            "unknown pass `{}`, expected one of: {}",
+            self.0,
+            names.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownPass {}
+
+/// Runs a set of passes over a program repeatedly until none of them change it
+pub struct PassManager {
+    /// Every available pass, and whether it is enabled
+    passes: Vec<(Box<dyn Pass>, bool)>,
+}
+
+impl PassManager {
+    /// The most times the passes will be run over a program while waiting for it to stop changing
+    pub const MAX_ITERATIONS: usize = 16;
+
+    /// Constructs a PassManager with the passes for the given optimization level enabled
+    pub fn new(level: OptLevel) -> Self {
+        let passes = all_passes()
+            .into_iter()
+            .map(|pass| {
+                let enabled = level >= pass.level();
+                (pass, enabled)
+            })
+            .collect();
+
+        PassManager { passes }
+    }
+
+    /// Adds an enabled pass, which runs after the passes already added
+    pub fn add_pass(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push((pass, true));
+    }
+
+    /// Enables the pass with the given name
+    pub fn enable(&mut self, name: &str) -> Result<(), UnknownPass> {
+        self.set_enabled(name, true)
+    }
+
+    /// Disables the pass with the given name
+    pub fn disable(&mut self, name: &str) -> Result<(), UnknownPass> {
+        self.set_enabled(name, false)
+    }
+
+    fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), UnknownPass> {
+        match self.passes.iter_mut().find(|(pass, _)| pass.name() == name) {
+            Some((_, pass_enabled)) => {
+                *pass_enabled = enabled;
+                Ok(())
+            }
+            None => Err(UnknownPass(name.to_string())),
+        }
+    }
+
+    /// The names of the enabled passes, in the order that they run
+    pub fn enabled(&self) -> Vec<&'static str> {
+        self.passes
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(pass, _)| pass.name())
+            .collect()
+    }
+
+    /// Run the enabled passes over the program until it stops changing
+    pub fn run(&self, program: &[Instruction]) -> Vec<Instruction> {
+        let mut optimized = program.to_vec();
+
+        for _ in 0..Self::MAX_ITERATIONS {
+            let mut changed = false;
+
+            for (pass, _) in self.passes.iter().filter(|(_, enabled)| *enabled) {
+                // Every pass relies on loop targets, so they must be relinked after each pass
+                let mut next = pass.run(&optimized);
+                link_loops(&mut next);
+
+                changed |= next != optimized;
+                optimized = next;
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        optimized
+    }
+}
+
+/// Optimize a parsed program with every pass, returning an equivalent program that runs faster
+pub fn optimize(program: &[Instruction]) -> Vec<Instruction> {
+    PassManager::new(OptLevel::O2).run(program)
+}
+
+/// Runs [`clear_loops`]
+pub struct ClearLoops;
+
+impl Pass for ClearLoops {
+    fn name(&self) -> &'static str {
+        "clear-loops"
+    }
+
+    fn level(&self) -> OptLevel {
+        OptLevel::O1
+    }
+
+    fn run(&self, program: &[Instruction]) -> Vec<Instruction> {
+        clear_loops(program)
+    }
+}
+
+/// Replace loops that only increment or decrement the current cell, such as `[-]` and `[+]`,
+/// with an instruction that sets the cell to zero. Any additions immediately following
+/// the loop are folded into the value that the cell is set to.
+pub fn clear_loops(program: &[Instruction]) -> Vec<Instruction> {
+    let mut optimized: Vec<Instruction> = Vec::with_capacity(program.len());
+
+    let mut index = 0;
+    while index < program.len() {
+        match &program[index..] {
+            // An odd delta will eventually reach zero from any starting value
+            [Instruction::LoopStart { .. }, Instruction::AddByte { offset: 0, delta }, Instruction::LoopEnd { .. }, ..]
+                if delta % 2 == 1 =>
+            {
+                // Changes to the cell just before it is cleared have no effect
+                while let Some(
+                    Instruction::AddByte { offset: 0, .. } | Instruction::SetByte { offset: 0, .. },
+                ) = optimized.last()
+                {
+                    optimized.pop();
+                }
+
+                optimized.push(Instruction::SetByte {
+                    offset: 0,
+                    value: 0,
+                });
+                index += 3;
+            }
+            [inst, ..] => {
+                let combined = match optimized.last_mut() {
+                    Some(prev @ Instruction::SetByte { .. }) => prev.combine(inst),
+                    _ => false,
+                };
+                if !combined {
+                    optimized.push(*inst);
+                }
+                index += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+
+    optimized
+}
+
+/// Runs [`multiply_loops`]
+pub struct MultiplyLoops;
+
+impl Pass for MultiplyLoops {
+    fn name(&self) -> &'static str {
+        "multiply-loops"
+    }
+
+    fn level(&self) -> OptLevel {
+        OptLevel::O2
+    }
+
+    fn run(&self, program: &[Instruction]) -> Vec<Instruction> {
+        multiply_loops(program)
+    }
+}
+
+/// Replace loops that move the pointer back to where it started, decrement or increment the
+/// current cell by one, and add constant amounts to other cells, such as `[->+>++<<]`.
+/// Each such loop adds a multiple of the current cell to the other cells and then clears it.
+pub fn multiply_loops(program: &[Instruction]) -> Vec<Instruction> {
+    let mut optimized: Vec<Instruction> = Vec::with_capacity(program.len());
+
+    let mut index = 0;
+    while index < program.len() {
+        if let Instruction::LoopStart { end } = program[index] {
+            if let Some(factors) = multiply_loop_factors(&program[index + 1..end]) {
+                for (offset, factor) in factors {
+                    optimized.push(Instruction::MulAdd { offset, factor });
+                }
+                optimized.push(Instruction::SetByte {
+                    offset: 0,
+                    value: 0,
+                });
+                index = end + 1;
+                continue;
+            }
+        }
+
+        optimized.push(program[index]);
+        index += 1;
+    }
+
+    optimized
+}
+
+/// If the loop body is a multiply loop, returns the amount that each iteration of the loop
+/// adds to other cells for each time the current cell is decremented, by offset from the pointer.
+fn multiply_loop_factors(body: &[Instruction]) -> Option<Vec<(isize, u64)>> {
+    // The pointer's position relative to its value when the loop starts
+    let mut pointer: isize = 0;
+
+    // The amount added to each cell by one iteration of the loop, in order of first appearance
+    let mut deltas: Vec<(isize, u64)> = Vec::new();
+
+    for inst in body {
+        let (cell, delta) = match inst {
+            Instruction::AddPtr { count } => {
+                pointer += *count as isize;
+                continue;
+            }
+            Instruction::SubPtr { count } => {
+                pointer -= *count as isize;
+                continue;
+            }
+            Instruction::AddByte { offset, delta } => (pointer + offset, *delta),
+            _ => return None,
+        };
+
+        match deltas.iter_mut().find(|(offset, _)| *offset == cell) {
+            Some((_, total)) => *total = total.wrapping_add(delta),
+            None => deltas.push((cell, delta)),
+        }
+    }
+
+    if pointer != 0 {
+        return None;
+    }
+
+    // The loop runs once per decrement of the current cell, or once per increment until it wraps
+    let counter = deltas
+        .iter()
+        .position(|(offset, _)| *offset == 0)
+        .map_or(0, |position| deltas.remove(position).1);
+    let negate = match counter {
+        u64::MAX => false,
+        1 => true,
+        _ => return None,
+    };
+
+    Some(
+        deltas
+            .into_iter()
+            .filter(|(_, delta)| *delta != 0)
+            .map(|(offset, delta)| {
+                let factor = if negate { delta.wrapping_neg() } else { delta };
+                (offset, factor)
+            })
+            .collect(),
+    )
+}
+
+/// Runs [`scan_loops`]
+pub struct ScanLoops;
+
+impl Pass for ScanLoops {
+    fn name(&self) -> &'static str {
+        "scan-loops"
+    }
+
+    fn level(&self) -> OptLevel {
+        OptLevel::O1
+    }
+
+    fn run(&self, program: &[Instruction]) -> Vec<Instruction> {
+        scan_loops(program)
+    }
+}
+
+/// Replace loops that only move the pointer, such as `[>]` and `[<<]`, with an instruction
+/// that searches for the nearest zero cell in that direction.
+pub fn scan_loops(program: &[Instruction]) -> Vec<Instruction> {
+    let mut optimized: Vec<Instruction> = Vec::with_capacity(program.len());
+
+    let mut index = 0;
+    while index < program.len() {
+        match &program[index..] {
+            [Instruction::LoopStart { .. }, Instruction::AddPtr { count }, Instruction::LoopEnd { .. }, ..] =>
+            {
+                optimized.push(Instruction::Scan {
+                    stride: *count as isize,
+                });
+                index += 3;
+            }
+            [Instruction::LoopStart { .. }, Instruction::SubPtr { count }, Instruction::LoopEnd { .. }, ..] =>
+            {
+                optimized.push(Instruction::Scan {
+                    stride: -(*count as isize),
+                });
+                index += 3;
+            }
+            _ => {
+                optimized.push(program[index]);
+                index += 1;
+            }
+        }
+    }
+
+    optimized
+}
+
+/// Runs [`defer_pointer_moves`]
+pub struct DeferPointerMoves;
+
+impl Pass for DeferPointerMoves {
+    fn name(&self) -> &'static str {
+        "defer-pointer-moves"
+    }
+
+    fn level(&self) -> OptLevel {
+        OptLevel::O2
+    }
+
+    fn run(&self, program: &[Instruction]) -> Vec<Instruction> {
+        defer_pointer_moves(program)
+    }
+}
+
+/// Track the pointer's position between loops and I/O instead of moving it after every `>`
+/// and `<`. Cell operations in between refer to cells by their offset from the pointer, and
+/// the pointer is only moved once, just before the next instruction that needs it.
+pub fn defer_pointer_moves(program: &[Instruction]) -> Vec<Instruction> {
+    let mut optimized: Vec<Instruction> = Vec::with_capacity(program.len());
+
+    // The distance that the pointer should have moved since it was last moved
+    let mut pointer: isize = 0;
+
+    // The index in `optimized` after the pointer was last moved
+    let mut region_start = 0;
+
+    for inst in program {
+        let inst = match *inst {
+            Instruction::AddPtr { count } => {
+                pointer += count as isize;
+                continue;
+            }
+            Instruction::SubPtr { count } => {
+                pointer -= count as isize;
+                continue;
+            }
+            Instruction::AddByte { offset, delta } => Instruction::AddByte {
+                offset: offset + pointer,
+                delta,
+            },
+            Instruction::SetByte { offset, value } => Instruction::SetByte {
+                offset: offset + pointer,
+                value,
+            },
+            inst => {
+                // Everything else uses the pointer, so it must be moved first
+                if pointer > 0 {
+                    optimized.push(Instruction::AddPtr {
+                        count: pointer as usize,
+                    });
+                } else if pointer < 0 {
+                    optimized.push(Instruction::SubPtr {
+                        count: pointer.unsigned_abs(),
+                    });
+                }
+                pointer = 0;
+
+                optimized.push(inst);
+                region_start = optimized.len();
+                continue;
+            }
+        };
+
+        // Cell operations in the same region all commute unless they refer to the same cell,
+        // so combine the new operation with any earlier one on the same cell.
+        let same_cell = optimized[region_start..].iter_mut().rev().find(|prev| {
+            matches!(
+                (prev, inst),
+                (Instruction::AddByte { offset: a, .. } | Instruction::SetByte { offset: a, .. },
+                 Instruction::AddByte { offset: b, .. } | Instruction::SetByte { offset: b, .. })
+                if *a == b
+            )
+        });
+        match same_cell {
+            Some(prev) => {
+                if !prev.combine(&inst) {
+                    *prev = inst;
+                }
+            }
+            None => optimized.push(inst),
+        }
+    }
+
+    optimized
+}
+
+/// Set the targets of every `LoopStart` and `LoopEnd` instruction to the index of the
+/// matching instruction. Passes that add or remove instructions must call this afterwards.
+pub fn link_loops(program: &mut [Instruction]) {
+    // Indices in `program` at which loops start
+    let mut loop_starts: Vec<usize> = Vec::new();
+
+    for index in 0..program.len() {
+        match program[index] {
+            Instruction::LoopStart { .. } => loop_starts.push(index),
+            Instruction::LoopEnd { .. } => {
+                let start = loop_starts.pop().expect("Unmatched loop end");
+                program[start].set_target(index);
+                program[index].set_target(start);
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,46 +1,153 @@
+use std::{error::Error, fmt};
+
 use crate::instruction::Instruction;
 
+/// The location of a single character in a Brainfuck source string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the character from the start of the source
+    pub offset: usize,
+    /// Length of the character in bytes
+    pub len: usize,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column number in characters, starting at 1
+    pub column: usize,
+}
+
+/// The two kinds of bracket that must be matched in a Brainfuck program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bracket {
+    Open,
+    Close,
+}
+
+/// A `[` or `]` that has no matching bracket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmatchedBracket {
+    pub bracket: Bracket,
+    pub span: Span,
+}
+
+/// The error returned when a Brainfuck source string cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Every unmatched bracket in the source, in source order
+    pub unmatched: Vec<UnmatchedBracket>,
+}
+
+impl fmt::Display for Bracket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bracket::Open => write!(f, "["),
+            Bracket::Close => write!(f, "]"),
+        }
+    }
+}
+
+impl fmt::Display for UnmatchedBracket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unmatched `{}` at line {}, column {}",
+            self.bracket, self.span.line, self.span.column
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, unmatched) in self.unmatched.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", unmatched)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
 /// Parse a Brainfuck source string
-pub fn parse(source: &str) -> Vec<Instruction> {
+pub fn parse(source: &str) -> Result<Vec<Instruction>, ParseError> {
     // The sequence of parsed instruction, to be returned
     let mut program: Vec<Instruction> = Vec::new();
 
-    // Indices in `program` at which loops start
-    let mut loop_starts: Vec<usize> = Vec::new();
+    // Indices in `program` at which loops start, along with their location in the source
+    let mut loop_starts: Vec<(usize, Span)> = Vec::new();
+
+    // Loop ends that had no matching loop start
+    let mut unmatched: Vec<UnmatchedBracket> = Vec::new();
 
-    for command in source.chars().filter(|c| "<>+-,.[]".contains(*c)) {
-        // Convert the character to an instruction
-        let mut next_inst = Instruction::from_char(command);
+    // The current position in the source
+    let mut line = 1;
+    let mut column = 1;
 
-        // If the new instruction is the same as the previous, just increment the count in the previous
+    for (offset, command) in source.char_indices() {
+        let span = Span {
+            offset,
+            len: command.len_utf8(),
+            line,
+            column,
+        };
+
+        if command == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+
+        // Convert the character to an instruction, skipping comments
+        let mut next_inst = match Instruction::from_char(command) {
+            Some(inst) => Some(inst),
+            None => continue,
+        };
+
+        // If the new instruction can be combined with the previous, just update the previous
         if let Some(prev_inst) = program.last_mut() {
-            if prev_inst.variant_eq(&next_inst.unwrap()) && prev_inst.can_increment() {
-                prev_inst.increment();
+            if prev_inst.combine(&next_inst.unwrap()) {
                 next_inst = None;
             }
         };
 
         // Set loop start / loop end targets
         if let Some(Instruction::LoopStart { .. }) = next_inst {
-            loop_starts.push(program.len());
+            loop_starts.push((program.len(), span));
         } else if let Some(Instruction::LoopEnd { start }) = &mut next_inst {
-            let loop_start = loop_starts.pop().expect("Unmatched loop end");
+            let (loop_start, _) = match loop_starts.pop() {
+                Some(loop_start) => loop_start,
+                None => {
+                    unmatched.push(UnmatchedBracket {
+                        bracket: Bracket::Close,
+                        span,
+                    });
+                    continue;
+                }
+            };
             *start = loop_start;
 
             let loop_end = program.len();
             program.get_mut(loop_start).unwrap().set_target(loop_end);
         }
 
-        // Add the next instruction to the program (as long as it wasn't the same as the previous)
+        // Add the next instruction to the program (as long as it wasn't combined with the previous)
         if let Some(i) = next_inst {
             program.push(i);
         }
     }
 
-    // Error if there are more loop starts than ends
-    if !loop_starts.is_empty() {
-        panic!("Unmatched loop start.");
+    // Any loop starts left over have no matching loop end
+    unmatched.extend(loop_starts.into_iter().map(|(_, span)| UnmatchedBracket {
+        bracket: Bracket::Open,
+        span,
+    }));
+
+    if !unmatched.is_empty() {
+        unmatched.sort_by_key(|u| u.span.offset);
+        return Err(ParseError { unmatched });
     }
 
-    program
+    Ok(program)
 }
@@ -0,0 +1,66 @@
+use std::{error::Error, fmt, io};
+
+pub mod assembler;
+pub mod machine;
+
+pub use crate::isa::Xlen;
+pub use assembler::{assemble, Program};
+pub use machine::Machine;
+
+/// The error returned when a program cannot be assembled or stops abnormally
+#[derive(Debug)]
+pub enum SimError {
+    /// The source could not be assembled
+    Assemble { line: usize, message: String },
+    /// The program did something that the machine cannot do, such as accessing unmapped memory
+    Fault { pc: u64, message: String },
+    /// The program ran for more instructions than allowed
+    StepLimit(u64),
+    /// Reading input or writing output failed
+    Io(io::Error),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::Assemble { line, message } => write!(f, "line {}: {}", line, message),
+            SimError::Fault { pc, message } => write!(f, "fault at pc {:#x}: {}", pc, message),
+            SimError::StepLimit(steps) => {
+                write!(f, "program did not exit after {} instructions", steps)
+            }
+            SimError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for SimError {}
+
+impl From<io::Error> for SimError {
+    fn from(error: io::Error) -> Self {
+        SimError::Io(error)
+    }
+}
+
+/// Everything that a program wrote before exiting, and its exit code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+/// Assemble and run a program, with `input` as its standard input
+pub fn run(source: &str, xlen: Xlen, input: &[u8]) -> Result<Output, SimError> {
+    let program = assemble(source, xlen)?;
+    let mut machine = Machine::new(&program);
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let exit_code = machine.run(&mut &input[..], &mut stdout, &mut stderr)?;
+
+    Ok(Output {
+        stdout,
+        stderr,
+        exit_code,
+    })
+}
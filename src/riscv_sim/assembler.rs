@@ -0,0 +1,1114 @@
+use std::collections::{HashMap, HashSet};
+
+use super::SimError;
+use crate::isa::{
+    self, fits_immediate, sign_extend, split_offset, AluOp, Arch, BranchOp, EncodeError, Inst,
+    Pending, Register, Width, Xlen, COMPRESSED_LENGTH, INSTRUCTION_LENGTH,
+};
+
+/// The address of the first instruction. The data and bss sections follow the text section.
+pub const TEXT_BASE: u64 = 0x1_0000;
+
+/// The encodings of `nop` and its compressed form, which pad the text section
+const NOP: [u8; 4] = 0x0000_0013u32.to_le_bytes();
+const COMPRESSED_NOP: [u8; 2] = 0x0001u16.to_le_bytes();
+
+/// The alignment of the start of each section
+const SECTION_ALIGNMENT: u64 = 0x1000;
+
+/// An assembled program, ready to be run by a Machine
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub xlen: Xlen,
+    /// The size in bytes of the text section, which starts at `TEXT_BASE`
+    pub text_size: u64,
+    /// The address and size in bytes of the data section
+    pub data_base: u64,
+    pub data_size: u64,
+    /// The address and size in bytes of the bss section, which is the end of the image
+    pub bss_base: u64,
+    pub bss_size: u64,
+    /// The initial contents of memory from `TEXT_BASE` to the end of the bss section, starting
+    /// with the encoded instructions of the text section
+    pub image: Vec<u8>,
+    /// The address at which execution starts: `_start`, `main`, or the first instruction
+    pub entry: u64,
+    /// The number of instructions in the text section that were compressed to 16 bits
+    pub compressed: usize,
+    /// The address of every label
+    pub symbols: HashMap<String, u64>,
+    /// The labels declared with `.globl`, which are visible to the linker
+    pub globals: HashSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Text,
+    Data,
+    Bss,
+}
+
+/// An item in the text section
+enum TextItem {
+    /// An instruction, the line that it came from, and whether it may be compressed
+    Instruction {
+        pending: Pending,
+        line: usize,
+        compressible: bool,
+    },
+    /// Padding to a multiple of the alignment, which depends on the size of the instructions
+    /// before it
+    Align(u64),
+}
+
+/// The state of the assembler while reading the source
+struct Assembler {
+    xlen: Xlen,
+    /// The ISA given by an `.attribute arch` directive, which instructions must be part of
+    arch: Arch,
+    /// Whether instructions may be compressed, and the values saved by `.option push`
+    rvc: bool,
+    saved_rvc: Vec<bool>,
+    section: Section,
+    text: Vec<TextItem>,
+    data: Vec<u8>,
+    bss_size: u64,
+    /// The section and offset of every label, where the offset of a label in the text section
+    /// is the index of the item that follows it
+    labels: HashMap<String, (Section, u64)>,
+    globals: HashSet<String>,
+}
+
+/// The addresses of the sections and labels, once the size of every instruction is known
+struct Layout {
+    /// The address of each item in the text section, followed by the end of the section
+    addresses: Vec<u64>,
+    data_base: u64,
+    bss_base: u64,
+    symbols: HashMap<String, u64>,
+}
+
+/// Assemble RISC-V assembly source, as generated by the compiler, into a Program.
+///
+/// As in GNU `as`, instructions are compressed where possible once an `.attribute arch`
+/// directive includes the C extension, or after `.option rvc`.
+pub fn assemble(source: &str, xlen: Xlen) -> Result<Program, SimError> {
+    let mut assembler = Assembler {
+        xlen,
+        arch: Arch::default_for(xlen),
+        rvc: false,
+        saved_rvc: Vec::new(),
+        section: Section::Text,
+        text: Vec::new(),
+        data: Vec::new(),
+        bss_size: 0,
+        labels: HashMap::new(),
+        globals: HashSet::new(),
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        assembler
+            .line(index + 1, line)
+            .map_err(|message| SimError::Assemble {
+                line: index + 1,
+                message,
+            })?;
+    }
+
+    assembler.finish()
+}
+
+/// The size in bytes of the instructions in `source` before any are compressed, which is the
+/// most space that they can take up once assembled. Labels do not need to be defined, and
+/// directives are ignored.
+pub fn code_size(source: &str, xlen: Xlen) -> Result<u64, SimError> {
+    let mut size = 0;
+    for (index, line) in source.lines().enumerate() {
+        let mut rest = strip_comment(line).trim();
+        while let Some((_, after)) = split_label(rest) {
+            rest = after.trim_start();
+        }
+        if rest.is_empty() || rest.starts_with('.') {
+            continue;
+        }
+
+        let (mnemonic, operands) = split_mnemonic(rest);
+        let instructions =
+            instruction(mnemonic, &split_operands(operands), xlen).map_err(|message| {
+                SimError::Assemble {
+                    line: index + 1,
+                    message,
+                }
+            })?;
+        size += instructions.len() as u64 * INSTRUCTION_LENGTH;
+    }
+    Ok(size)
+}
+
+impl Assembler {
+    /// Assemble a single line of source, with the given line number
+    fn line(&mut self, number: usize, line: &str) -> Result<(), String> {
+        let mut rest = strip_comment(line).trim();
+
+        // Any number of labels can come before a directive or instruction
+        while let Some((label, after)) = split_label(rest) {
+            let location = (self.section, self.offset());
+            if self.labels.insert(label.to_string(), location).is_some() {
+                return Err(format!("label `{}` is defined more than once", label));
+            }
+            rest = after.trim_start();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, operands) = split_mnemonic(rest);
+        let operands = split_operands(operands);
+
+        if mnemonic.starts_with('.') {
+            self.directive(mnemonic, &operands)
+        } else if self.section == Section::Text {
+            for pending in instruction(mnemonic, &operands, self.xlen)? {
+                if let Pending::Ready(inst) = pending {
+                    if !self.arch.supports(inst) {
+                        return Err(format!(
+                            "`{}` is not part of the {} ISA",
+                            mnemonic,
+                            self.arch.name()
+                        ));
+                    }
+                }
+                self.text.push(TextItem::Instruction {
+                    pending,
+                    line: number,
+                    compressible: self.rvc,
+                });
+            }
+            Ok(())
+        } else {
+            Err(format!(
+                "instruction `{}` outside of the text section",
+                mnemonic
+            ))
+        }
+    }
+
+    /// The offset of the next byte in the current section, or the index of the next item in the
+    /// text section
+    fn offset(&self) -> u64 {
+        match self.section {
+            Section::Text => self.text.len() as u64,
+            Section::Data => self.data.len() as u64,
+            Section::Bss => self.bss_size,
+        }
+    }
+
+    /// Add `size` zero bytes to the current section
+    fn reserve(&mut self, size: u64) -> Result<(), String> {
+        match self.section {
+            Section::Text if size.is_multiple_of(INSTRUCTION_LENGTH) => {
+                let nop = Inst::OpImm {
+                    op: AluOp::Add,
+                    rd: Register::ZERO,
+                    rs1: Register::ZERO,
+                    imm: 0,
+                    word: false,
+                };
+                for _ in 0..size / INSTRUCTION_LENGTH {
+                    self.text.push(TextItem::Instruction {
+                        pending: Pending::Ready(nop),
+                        line: 0,
+                        compressible: false,
+                    });
+                }
+            }
+            Section::Text => return Err("data in the text section".to_string()),
+            Section::Data => self.data.resize(self.data.len() + size as usize, 0),
+            Section::Bss => self.bss_size += size,
+        }
+        Ok(())
+    }
+
+    /// Add initialized bytes to the current section
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), String> {
+        match self.section {
+            Section::Data => {
+                self.data.extend_from_slice(bytes);
+                Ok(())
+            }
+            Section::Text => Err("data in the text section".to_string()),
+            Section::Bss => Err("initialized data in the bss section".to_string()),
+        }
+    }
+
+    /// Pad the current section to a multiple of `alignment` bytes
+    fn align(&mut self, alignment: u64) -> Result<(), String> {
+        if alignment == 0 || !alignment.is_power_of_two() {
+            return Err(format!("invalid alignment {}", alignment));
+        }
+        if self.section == Section::Text {
+            self.text.push(TextItem::Align(alignment));
+            return Ok(());
+        }
+        let offset = self.offset();
+        self.reserve(align_up(offset, alignment) - offset)
+    }
+
+    /// Assemble a directive
+    fn directive(&mut self, directive: &str, operands: &[&str]) -> Result<(), String> {
+        match directive {
+            ".text" => self.section = Section::Text,
+            ".data" | ".rodata" => self.section = Section::Data,
+            ".bss" => self.section = Section::Bss,
+            ".section" => {
+                let name = operands.first().ok_or("missing section name")?;
+                self.section = match *name {
+                    ".text" => Section::Text,
+                    ".data" | ".rodata" | ".sdata" => Section::Data,
+                    ".bss" | ".sbss" => Section::Bss,
+                    _ => return Err(format!("unknown section `{}`", name)),
+                }
+            }
+            ".globl" | ".global" => {
+                for name in operands {
+                    self.globals.insert(name.to_string());
+                }
+            }
+            ".attribute" => {
+                // Tag 5 is the ISA string, and the other attributes do not affect assembly
+                if let [tag, value] = operands {
+                    if *tag == "arch" || *tag == "5" {
+                        let isa = value.trim_matches('"');
+                        let arch = Arch::parse(isa)
+                            .ok_or_else(|| format!("invalid ISA string `{}`", isa))?;
+                        if arch.xlen != self.xlen {
+                            return Err(format!("`{}` is not an RV{} ISA", isa, self.xlen.bits()));
+                        }
+                        self.arch = arch;
+                        self.rvc = arch.c;
+                    }
+                }
+            }
+            ".option" => match operands {
+                ["rvc"] => self.rvc = true,
+                ["norvc"] => self.rvc = false,
+                ["push"] => self.saved_rvc.push(self.rvc),
+                ["pop"] => self.rvc = self.saved_rvc.pop().ok_or("`.option pop` without push")?,
+                _ => {}
+            },
+            ".local" | ".type" | ".size" | ".file" => {}
+            ".align" | ".p2align" => {
+                let power = single(operands).and_then(immediate)?;
+                if !(0..64).contains(&power) {
+                    return Err(format!("invalid alignment 2^{}", power));
+                }
+                self.align(1 << power)?;
+            }
+            ".balign" => {
+                let alignment = single(operands).and_then(immediate)?;
+                self.align(alignment as u64)?;
+            }
+            ".space" | ".zero" | ".skip" => {
+                let size = single(operands).and_then(immediate)?;
+                if size < 0 {
+                    return Err(format!("invalid size {}", size));
+                }
+                self.reserve(size as u64)?;
+            }
+            ".byte" | ".half" | ".short" | ".2byte" | ".word" | ".long" | ".4byte" | ".dword"
+            | ".quad" | ".8byte" => {
+                let size = match directive {
+                    ".byte" => 1,
+                    ".half" | ".short" | ".2byte" => 2,
+                    ".word" | ".long" | ".4byte" => 4,
+                    _ => 8,
+                };
+                for operand in operands {
+                    let value = immediate(operand)?;
+                    self.emit(&value.to_le_bytes()[..size])?;
+                }
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for operand in operands {
+                    let mut bytes = string_literal(operand)?;
+                    if directive != ".ascii" {
+                        bytes.push(0);
+                    }
+                    self.emit(&bytes)?;
+                }
+            }
+            _ => return Err(format!("unknown directive `{}`", directive)),
+        }
+        Ok(())
+    }
+
+    /// Lay out the sections, with the given items of the text section compressed
+    fn layout(&self, compressed: &[bool]) -> Layout {
+        let mut addresses = Vec::with_capacity(self.text.len() + 1);
+        let mut address = TEXT_BASE;
+        for (item, compressed) in self.text.iter().zip(compressed) {
+            addresses.push(address);
+            address += match item {
+                TextItem::Instruction { .. } if *compressed => COMPRESSED_LENGTH,
+                TextItem::Instruction { .. } => INSTRUCTION_LENGTH,
+                TextItem::Align(alignment) => align_up(address, *alignment) - address,
+            };
+        }
+        addresses.push(address);
+
+        let data_base = align_up(address, SECTION_ALIGNMENT);
+        let bss_base = align_up(data_base + self.data.len() as u64, SECTION_ALIGNMENT);
+        let symbols = self
+            .labels
+            .iter()
+            .map(|(label, (section, offset))| {
+                let address = match section {
+                    Section::Text => addresses[*offset as usize],
+                    Section::Data => data_base + offset,
+                    Section::Bss => bss_base + offset,
+                };
+                (label.clone(), address)
+            })
+            .collect();
+
+        Layout {
+            addresses,
+            data_base,
+            bss_base,
+            symbols,
+        }
+    }
+
+    /// Choose which instructions to compress. Branches and jumps to labels start out
+    /// compressed, and are lengthened while their targets are out of range. Instructions are
+    /// never shortened again, so this always finishes.
+    fn compress(&self) -> (Vec<bool>, Layout) {
+        let mut compressed: Vec<bool> = self
+            .text
+            .iter()
+            .map(|item| match item {
+                TextItem::Instruction {
+                    pending,
+                    compressible: true,
+                    ..
+                } => match pending {
+                    Pending::Ready(inst) => isa::compress(*inst, self.xlen).is_some(),
+                    Pending::Branch { .. } | Pending::Jal { .. } => true,
+                    _ => false,
+                },
+                _ => false,
+            })
+            .collect();
+
+        loop {
+            let layout = self.layout(&compressed);
+            let mut changed = false;
+            for (index, item) in self.text.iter().enumerate() {
+                if let TextItem::Instruction { pending, .. } = item {
+                    let fits = || {
+                        pending
+                            .resolve(layout.addresses[index], &layout.symbols)
+                            .ok()
+                            .and_then(|inst| isa::compress(inst, self.xlen))
+                            .is_some()
+                    };
+                    if compressed[index] && !fits() {
+                        compressed[index] = false;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return (compressed, layout);
+            }
+        }
+    }
+
+    /// Lay out the sections, resolve labels, and build the Program
+    fn finish(self) -> Result<Program, SimError> {
+        let (compressed, layout) = self.compress();
+        let Layout {
+            addresses,
+            data_base,
+            bss_base,
+            symbols,
+        } = layout;
+        let text_size = addresses[self.text.len()] - TEXT_BASE;
+        let data_size = self.data.len() as u64;
+        let bss_size = self.bss_size;
+        let end = bss_base + bss_size;
+
+        let xlen = self.xlen;
+        let mut image = vec![0; (end - TEXT_BASE) as usize];
+        for (index, item) in self.text.iter().enumerate() {
+            let pc = addresses[index];
+            let start = (pc - TEXT_BASE) as usize;
+            match item {
+                TextItem::Instruction { pending, line, .. } => {
+                    let error = |error: EncodeError| SimError::Assemble {
+                        line: *line,
+                        message: error.to_string(),
+                    };
+                    let inst = pending.resolve(pc, &symbols).map_err(error)?;
+                    if compressed[index] {
+                        let half = isa::compress(inst, xlen)
+                            .expect("compressed instructions fit after layout");
+                        image[start..start + 2].copy_from_slice(&half.to_le_bytes());
+                    } else {
+                        let word = isa::encode(inst, xlen).map_err(error)?;
+                        image[start..start + 4].copy_from_slice(&word.to_le_bytes());
+                    }
+                }
+                TextItem::Align(_) => {
+                    // Pad with `nop`, then a compressed `nop` if a halfword is left
+                    let padding = (addresses[index + 1] - pc) as usize;
+                    for offset in (0..padding).step_by(INSTRUCTION_LENGTH as usize) {
+                        let nop: &[u8] = if padding - offset >= 4 {
+                            &NOP
+                        } else {
+                            &COMPRESSED_NOP
+                        };
+                        image[start + offset..start + offset + nop.len()].copy_from_slice(nop);
+                    }
+                }
+            }
+        }
+
+        let data_start = (data_base - TEXT_BASE) as usize;
+        image[data_start..data_start + self.data.len()].copy_from_slice(&self.data);
+
+        let entry = ["_start", "main"]
+            .iter()
+            .find_map(|name| symbols.get(*name).copied())
+            .unwrap_or(TEXT_BASE);
+
+        Ok(Program {
+            xlen,
+            text_size,
+            data_base,
+            data_size,
+            bss_base,
+            bss_size,
+            image,
+            entry,
+            compressed: compressed.iter().filter(|compressed| **compressed).count(),
+            symbols,
+            globals: self.globals,
+        })
+    }
+}
+
+/// Round `value` up to a multiple of `alignment`, which must be a power of two
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// Assemble an instruction or pseudo-instruction
+fn instruction(mnemonic: &str, operands: &[&str], xlen: Xlen) -> Result<Vec<Pending>, String> {
+    let rv64_only = matches!(
+        mnemonic,
+        "ld" | "sd"
+            | "lwu"
+            | "addw"
+            | "subw"
+            | "sllw"
+            | "srlw"
+            | "sraw"
+            | "mulw"
+            | "divw"
+            | "divuw"
+            | "remw"
+            | "remuw"
+            | "addiw"
+            | "slliw"
+            | "srliw"
+            | "sraiw"
+            | "negw"
+            | "sext.w"
+    );
+    if rv64_only && xlen == Xlen::Rv32 {
+        return Err(format!("`{}` is only available on RV64", mnemonic));
+    }
+
+    let ready = |inst: Inst| Ok(vec![Pending::Ready(inst)]);
+    let arity = |count: usize| -> Result<(), String> {
+        if operands.len() == count {
+            Ok(())
+        } else {
+            Err(format!(
+                "`{}` expects {} operand{}, found {}",
+                mnemonic,
+                count,
+                if count == 1 { "" } else { "s" },
+                operands.len()
+            ))
+        }
+    };
+
+    if let Some((op, word)) = register_op(mnemonic) {
+        arity(3)?;
+        return ready(Inst::Op {
+            op,
+            rd: register(operands[0])?,
+            rs1: register(operands[1])?,
+            rs2: register(operands[2])?,
+            word,
+        });
+    }
+
+    if let Some((op, word)) = immediate_op(mnemonic) {
+        arity(3)?;
+        let imm = immediate(operands[2])?;
+        let shift = matches!(op, AluOp::Sll | AluOp::Srl | AluOp::Sra);
+        let valid = if shift {
+            let bits = if word { 32 } else { xlen.bits() as i64 };
+            (0..bits).contains(&imm)
+        } else {
+            fits_immediate(imm)
+        };
+        if !valid {
+            return Err(format!(
+                "immediate {} is out of range for `{}`",
+                imm, mnemonic
+            ));
+        }
+        return ready(Inst::OpImm {
+            op,
+            rd: register(operands[0])?,
+            rs1: register(operands[1])?,
+            imm,
+            word,
+        });
+    }
+
+    if let Some(width) = load_width(mnemonic) {
+        arity(2)?;
+        let (offset, rs1) = memory_operand(operands[1])?;
+        return ready(Inst::Load {
+            width,
+            rd: register(operands[0])?,
+            rs1,
+            offset,
+        });
+    }
+
+    if let Some(width) = store_width(mnemonic) {
+        arity(2)?;
+        let (offset, rs1) = memory_operand(operands[1])?;
+        return ready(Inst::Store {
+            width,
+            rs1,
+            rs2: register(operands[0])?,
+            offset,
+        });
+    }
+
+    // Branches, including those that compare against zero or swap their operands
+    let branch = match mnemonic {
+        "beq" => Some((BranchOp::Beq, false, false)),
+        "bne" => Some((BranchOp::Bne, false, false)),
+        "blt" => Some((BranchOp::Blt, false, false)),
+        "bge" => Some((BranchOp::Bge, false, false)),
+        "bltu" => Some((BranchOp::Bltu, false, false)),
+        "bgeu" => Some((BranchOp::Bgeu, false, false)),
+        "bgt" => Some((BranchOp::Blt, true, false)),
+        "ble" => Some((BranchOp::Bge, true, false)),
+        "bgtu" => Some((BranchOp::Bltu, true, false)),
+        "bleu" => Some((BranchOp::Bgeu, true, false)),
+        "beqz" => Some((BranchOp::Beq, false, true)),
+        "bnez" => Some((BranchOp::Bne, false, true)),
+        "bltz" => Some((BranchOp::Blt, false, true)),
+        "bgez" => Some((BranchOp::Bge, false, true)),
+        "blez" => Some((BranchOp::Bge, true, true)),
+        "bgtz" => Some((BranchOp::Blt, true, true)),
+        _ => None,
+    };
+    if let Some((op, swap, zero)) = branch {
+        let (rs1, rs2, target) = if zero {
+            arity(2)?;
+            (register(operands[0])?, Register::ZERO, operands[1])
+        } else {
+            arity(3)?;
+            (register(operands[0])?, register(operands[1])?, operands[2])
+        };
+        let (rs1, rs2) = if swap { (rs2, rs1) } else { (rs1, rs2) };
+        return Ok(vec![Pending::Branch {
+            op,
+            rs1,
+            rs2,
+            target: target.to_string(),
+        }]);
+    }
+
+    match mnemonic {
+        "lui" | "auipc" => {
+            arity(2)?;
+            let rd = register(operands[0])?;
+            let imm = immediate(operands[1])?;
+            if !(0..1 << 20).contains(&imm) {
+                return Err(format!(
+                    "immediate {} is out of range for `{}`",
+                    imm, mnemonic
+                ));
+            }
+            let imm = sign_extend(imm, 20);
+            if mnemonic == "lui" {
+                ready(Inst::Lui { rd, imm })
+            } else {
+                ready(Inst::Auipc { rd, imm })
+            }
+        }
+        "jal" => {
+            let (rd, target) = match operands.len() {
+                1 => (Register::RA, operands[0]),
+                _ => {
+                    arity(2)?;
+                    (register(operands[0])?, operands[1])
+                }
+            };
+            Ok(vec![Pending::Jal {
+                rd,
+                target: target.to_string(),
+            }])
+        }
+        "j" => {
+            arity(1)?;
+            Ok(vec![Pending::Jal {
+                rd: Register::ZERO,
+                target: operands[0].to_string(),
+            }])
+        }
+        "call" | "tail" => {
+            arity(1)?;
+            let (rd, scratch) = if mnemonic == "call" {
+                (Register::RA, Register::RA)
+            } else {
+                (Register::ZERO, Register::T1)
+            };
+            let target = operands[0].to_string();
+            Ok(vec![
+                Pending::PcRelHigh {
+                    rd: scratch,
+                    target: target.clone(),
+                },
+                Pending::PcRelLow {
+                    jump: true,
+                    rd,
+                    rs1: scratch,
+                    target,
+                },
+            ])
+        }
+        "jalr" => {
+            let (rd, rs1, offset) = match operands.len() {
+                1 => (Register::RA, register(operands[0])?, 0),
+                2 => {
+                    let (offset, rs1) = memory_operand(operands[1])?;
+                    (register(operands[0])?, rs1, offset)
+                }
+                _ => {
+                    arity(3)?;
+                    let offset = immediate(operands[2])?;
+                    if !fits_immediate(offset) {
+                        return Err(format!("immediate {} is out of range for `jalr`", offset));
+                    }
+                    (register(operands[0])?, register(operands[1])?, offset)
+                }
+            };
+            ready(Inst::Jalr { rd, rs1, offset })
+        }
+        "jr" => {
+            arity(1)?;
+            ready(Inst::Jalr {
+                rd: Register::ZERO,
+                rs1: register(operands[0])?,
+                offset: 0,
+            })
+        }
+        "ret" => {
+            arity(0)?;
+            ready(Inst::Jalr {
+                rd: Register::ZERO,
+                rs1: Register::RA,
+                offset: 0,
+            })
+        }
+        "ecall" => {
+            arity(0)?;
+            ready(Inst::Ecall)
+        }
+        "nop" => {
+            arity(0)?;
+            ready(Inst::OpImm {
+                op: AluOp::Add,
+                rd: Register::ZERO,
+                rs1: Register::ZERO,
+                imm: 0,
+                word: false,
+            })
+        }
+        "li" => {
+            arity(2)?;
+            let rd = register(operands[0])?;
+            let value = immediate(operands[1])?;
+            let value = match xlen {
+                Xlen::Rv32 if value > u32::MAX as i64 || value < i32::MIN as i64 => {
+                    return Err(format!("immediate {} does not fit in a register", value))
+                }
+                Xlen::Rv32 => value as i32 as i64,
+                Xlen::Rv64 => value,
+            };
+            Ok(load_immediate(rd, value, xlen)
+                .into_iter()
+                .map(Pending::Ready)
+                .collect())
+        }
+        "la" | "lla" => {
+            arity(2)?;
+            let rd = register(operands[0])?;
+            let target = operands[1].to_string();
+            Ok(vec![
+                Pending::PcRelHigh {
+                    rd,
+                    target: target.clone(),
+                },
+                Pending::PcRelLow {
+                    jump: false,
+                    rd,
+                    rs1: rd,
+                    target,
+                },
+            ])
+        }
+        // Pseudo-instructions that are a single instruction with a fixed operand
+        "mv" | "not" | "neg" | "negw" | "sext.w" | "seqz" | "snez" | "sltz" | "sgtz" => {
+            arity(2)?;
+            let rd = register(operands[0])?;
+            let rs = register(operands[1])?;
+            let op_imm = |op, imm, word| Inst::OpImm {
+                op,
+                rd,
+                rs1: rs,
+                imm,
+                word,
+            };
+            let op = |op, rs1, rs2, word| Inst::Op {
+                op,
+                rd,
+                rs1,
+                rs2,
+                word,
+            };
+            ready(match mnemonic {
+                "mv" => op_imm(AluOp::Add, 0, false),
+                "not" => op_imm(AluOp::Xor, -1, false),
+                "neg" => op(AluOp::Sub, Register::ZERO, rs, false),
+                "negw" => op(AluOp::Sub, Register::ZERO, rs, true),
+                "sext.w" => op_imm(AluOp::Add, 0, true),
+                "seqz" => op_imm(AluOp::Sltu, 1, false),
+                "snez" => op(AluOp::Sltu, Register::ZERO, rs, false),
+                "sltz" => op(AluOp::Slt, rs, Register::ZERO, false),
+                _ => op(AluOp::Slt, Register::ZERO, rs, false),
+            })
+        }
+        _ => Err(format!("unknown instruction `{}`", mnemonic)),
+    }
+}
+
+/// The operation of a register-register instruction, and whether it is a `*w` form
+fn register_op(mnemonic: &str) -> Option<(AluOp, bool)> {
+    let (base, word) = match mnemonic.strip_suffix('w') {
+        Some(base) => (base, true),
+        _ => (mnemonic, false),
+    };
+    let op = match base {
+        "add" => AluOp::Add,
+        "sub" => AluOp::Sub,
+        "sll" => AluOp::Sll,
+        "slt" if !word => AluOp::Slt,
+        "sltu" if !word => AluOp::Sltu,
+        "xor" if !word => AluOp::Xor,
+        "srl" => AluOp::Srl,
+        "sra" => AluOp::Sra,
+        "or" if !word => AluOp::Or,
+        "and" if !word => AluOp::And,
+        "mul" => AluOp::Mul,
+        "mulh" if !word => AluOp::Mulh,
+        "mulhsu" if !word => AluOp::Mulhsu,
+        "mulhu" if !word => AluOp::Mulhu,
+        "div" => AluOp::Div,
+        "divu" => AluOp::Divu,
+        "rem" => AluOp::Rem,
+        "remu" => AluOp::Remu,
+        _ => return None,
+    };
+    Some((op, word))
+}
+
+/// The operation of a register-immediate instruction, and whether it is a `*w` form
+fn immediate_op(mnemonic: &str) -> Option<(AluOp, bool)> {
+    let op = match mnemonic {
+        "addi" | "addiw" => AluOp::Add,
+        "slti" => AluOp::Slt,
+        "sltiu" => AluOp::Sltu,
+        "xori" => AluOp::Xor,
+        "ori" => AluOp::Or,
+        "andi" => AluOp::And,
+        "slli" | "slliw" => AluOp::Sll,
+        "srli" | "srliw" => AluOp::Srl,
+        "srai" | "sraiw" => AluOp::Sra,
+        _ => return None,
+    };
+    Some((op, mnemonic.ends_with('w')))
+}
+
+/// The width of a load instruction
+fn load_width(mnemonic: &str) -> Option<Width> {
+    match mnemonic {
+        "lb" => Some(Width::Byte),
+        "lbu" => Some(Width::ByteUnsigned),
+        "lh" => Some(Width::Half),
+        "lhu" => Some(Width::HalfUnsigned),
+        "lw" => Some(Width::Word),
+        "lwu" => Some(Width::WordUnsigned),
+        "ld" => Some(Width::Double),
+        _ => None,
+    }
+}
+
+/// The width of a store instruction
+fn store_width(mnemonic: &str) -> Option<Width> {
+    match mnemonic {
+        "sb" => Some(Width::Byte),
+        "sh" => Some(Width::Half),
+        "sw" => Some(Width::Word),
+        "sd" => Some(Width::Double),
+        _ => None,
+    }
+}
+
+/// The instructions that load the constant `value` into `rd`, as expanded by GNU `as`
+fn load_immediate(rd: Register, value: i64, xlen: Xlen) -> Vec<Inst> {
+    let addi = |rs1, imm, word| Inst::OpImm {
+        op: AluOp::Add,
+        rd,
+        rs1,
+        imm,
+        word,
+    };
+
+    if fits_immediate(value) {
+        return vec![addi(Register::ZERO, value, false)];
+    }
+
+    if value == value as i32 as i64 {
+        // On RV64, `lui` sign-extends, so `addiw` is needed to correct values near i32::MAX
+        let (high, low) = split_offset(value);
+        let mut insts = vec![Inst::Lui { rd, imm: high }];
+        if low != 0 {
+            insts.push(addi(rd, low, xlen == Xlen::Rv64));
+        }
+        return insts;
+    }
+
+    // Load the upper bits, then shift them into place and add the lowest 12 bits
+    let low = sign_extend(value, 12);
+    let high = (value - low) >> 12;
+    let shift = 12 + high.trailing_zeros();
+    let high = sign_extend(high >> (shift - 12), 64 - shift);
+
+    let mut insts = load_immediate(rd, high, xlen);
+    insts.push(Inst::OpImm {
+        op: AluOp::Sll,
+        rd,
+        rs1: rd,
+        imm: shift as i64,
+        word: false,
+    });
+    if low != 0 {
+        insts.push(addi(rd, low, false));
+    }
+    insts
+}
+
+/// Remove a `#` comment from the end of a line
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split a leading `label:` from the rest of a line
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    let mut chars = label.chars();
+    let first = chars.next()?;
+    let is_symbol = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$';
+    if (is_symbol(first) && !first.is_ascii_digit()) && chars.all(is_symbol) {
+        Some((label, rest))
+    } else {
+        None
+    }
+}
+
+/// Split a directive or instruction into its mnemonic and operands
+fn split_mnemonic(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    }
+}
+
+/// Split operands on the commas between them, ignoring commas in strings
+fn split_operands(operands: &str) -> Vec<&str> {
+    if operands.is_empty() {
+        return Vec::new();
+    }
+
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in operands.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                result.push(operands[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(operands[start..].trim());
+    result
+}
+
+/// The only operand of a directive
+fn single<'a>(operands: &[&'a str]) -> Result<&'a str, String> {
+    match operands {
+        [operand] => Ok(operand),
+        _ => Err(format!("expected 1 operand, found {}", operands.len())),
+    }
+}
+
+/// Parse a register operand
+fn register(operand: &str) -> Result<Register, String> {
+    Register::from_name(operand).ok_or_else(|| format!("invalid register `{}`", operand))
+}
+
+/// Parse an integer operand in decimal, hexadecimal, or binary, or a character literal
+fn immediate(operand: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid immediate `{}`", operand);
+
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, operand),
+    };
+    let magnitude = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16).map_err(|_| invalid())?
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        u64::from_str_radix(binary, 2).map_err(|_| invalid())?
+    } else if digits.starts_with('\'') && digits.ends_with('\'') && digits.len() >= 3 {
+        let bytes = unescape(&digits[1..digits.len() - 1]).map_err(|_| invalid())?;
+        match bytes[..] {
+            [byte] => byte as u64,
+            _ => return Err(invalid()),
+        }
+    } else {
+        digits.parse::<u64>().map_err(|_| invalid())?
+    };
+
+    // Large hexadecimal constants such as 0xffffffffffffffff are allowed to wrap
+    Ok(if negative {
+        (magnitude as i64).wrapping_neg()
+    } else {
+        magnitude as i64
+    })
+}
+
+/// Parse a memory operand of the form `offset(register)` or `(register)`
+fn memory_operand(operand: &str) -> Result<(i64, Register), String> {
+    let invalid = || format!("invalid memory operand `{}`", operand);
+
+    let (offset, rest) = operand.split_once('(').ok_or_else(invalid)?;
+    let base = rest.strip_suffix(')').ok_or_else(invalid)?;
+    let offset = match offset.trim() {
+        "" => 0,
+        offset => immediate(offset)?,
+    };
+    if !fits_immediate(offset) {
+        return Err(format!("offset {} is out of range", offset));
+    }
+    Ok((offset, register(base.trim())?))
+}
+
+/// Parse a double-quoted string literal
+fn string_literal(operand: &str) -> Result<Vec<u8>, String> {
+    operand
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .ok_or_else(|| format!("invalid string `{}`", operand))
+        .and_then(unescape)
+}
+
+/// Replace the escape sequences in a string with the bytes that they represent
+fn unescape(string: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        let escaped = chars.next().ok_or("unterminated escape sequence")?;
+        match escaped {
+            'n' => bytes.push(b'\n'),
+            't' => bytes.push(b'\t'),
+            'r' => bytes.push(b'\r'),
+            '0' => bytes.push(0),
+            '\\' | '\'' | '"' => bytes.push(escaped as u8),
+            'x' => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&digits, 16)
+                    .map_err(|_| format!("invalid escape sequence `\\x{}`", digits))?;
+                bytes.push(byte);
+            }
+            'u' => {
+                // Rust's `\u{...}` form, as produced by `escape_default`
+                let rest = chars.as_str();
+                let (code, after) = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .ok_or("invalid unicode escape sequence")?;
+                let c = u32::from_str_radix(code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or("invalid unicode escape sequence")?;
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                chars = after.chars();
+            }
+            _ => return Err(format!("invalid escape sequence `\\{}`", escaped)),
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,414 @@
+use std::{
+    convert::TryInto,
+    io::{self, Read, Write},
+};
+
+use super::{
+    assembler::{Program, TEXT_BASE},
+    SimError,
+};
+use crate::isa::{
+    self, AluOp, BranchOp, Inst, Register, Xlen, COMPRESSED_LENGTH, INSTRUCTION_LENGTH,
+};
+
+/// The size of the stack, which follows the program's sections in memory
+const STACK_SIZE: usize = 0x1_0000;
+
+/// System call numbers shared by Linux and RARS
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+/// RARS environment call numbers
+const RARS_EXIT: u64 = 10;
+const RARS_PRINT_CHAR: u64 = 11;
+const RARS_READ_CHAR: u64 = 12;
+
+/// The error returned by system calls on a file descriptor that is not open
+const EBADF: i64 = -9;
+
+/// The error returned by system calls given a buffer outside of memory
+const EFAULT: i64 = -14;
+
+/// The streams that a program reads from and writes to
+struct Streams<'a> {
+    stdin: &'a mut dyn Read,
+    stdout: &'a mut dyn Write,
+    stderr: &'a mut dyn Write,
+}
+
+/// A RISC-V hart with flat memory that runs an assembled Program
+pub struct Machine<'a> {
+    program: &'a Program,
+    /// The instruction starting at each halfword of the text section and its length in bytes,
+    /// or None where there is no valid instruction
+    text: Vec<Option<(Inst, u64)>>,
+    registers: [u64; 32],
+    pc: u64,
+    /// Memory from `TEXT_BASE` to the top of the stack
+    memory: Vec<u8>,
+    /// The number of instructions executed so far
+    pub steps: u64,
+    /// The number of instructions after which to stop, if any
+    pub step_limit: Option<u64>,
+}
+
+impl<'a> Machine<'a> {
+    /// Constructs a Machine that is ready to run `program` from its entry point
+    pub fn new(program: &'a Program) -> Self {
+        let mut memory = program.image.clone();
+        memory.resize(memory.len() + STACK_SIZE, 0);
+
+        // Instructions are decoded once, so the text section must not be modified. Compressed
+        // instructions can start at any halfword, so every halfword is decoded.
+        let text_size = program.text_size as usize;
+        let text = (0..text_size)
+            .step_by(COMPRESSED_LENGTH as usize)
+            .map(|offset| {
+                let half = u16::from_le_bytes([memory[offset], memory[offset + 1]]);
+                if half & 0b11 != 0b11 {
+                    return isa::expand(half, program.xlen).map(|inst| (inst, COMPRESSED_LENGTH));
+                }
+                let word = memory.get(offset..offset + INSTRUCTION_LENGTH as usize)?;
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                isa::decode(word, program.xlen).map(|inst| (inst, INSTRUCTION_LENGTH))
+            })
+            .collect();
+
+        let mut machine = Machine {
+            program,
+            text,
+            registers: [0; 32],
+            pc: program.entry,
+            memory,
+            steps: 0,
+            step_limit: None,
+        };
+        let stack_top = (TEXT_BASE + machine.memory.len() as u64) & !0xf;
+        machine.set_register(Register::SP, stack_top);
+        machine
+    }
+
+    /// The value of a register, which is sign-extended on RV32
+    pub fn register(&self, register: Register) -> u64 {
+        self.registers[register.0 as usize]
+    }
+
+    /// Run until the program exits, returning its exit code.
+    ///
+    /// Both the RARS and Linux system call conventions are supported: `read` (63), `write` (64),
+    /// and `exit` (93), along with the RARS Exit (10), PrintChar (11), and ReadChar (12) calls.
+    /// ReadChar returns -1 at the end of input. As in RARS, running past the last instruction
+    /// exits with code 0.
+    pub fn run(
+        &mut self,
+        stdin: &mut dyn Read,
+        stdout: &mut dyn Write,
+        stderr: &mut dyn Write,
+    ) -> Result<i32, SimError> {
+        let mut streams = Streams {
+            stdin,
+            stdout,
+            stderr,
+        };
+        let result = loop {
+            match self.step(&mut streams) {
+                Ok(Some(code)) => break Ok(code),
+                Ok(None) => {}
+                Err(error) => break Err(error),
+            }
+        };
+        streams.stdout.flush()?;
+        streams.stderr.flush()?;
+        result
+    }
+
+    /// Execute a single instruction, returning the exit code if the program exited
+    fn step(&mut self, streams: &mut Streams) -> Result<Option<i32>, SimError> {
+        if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+            return Err(SimError::StepLimit(self.steps));
+        }
+        self.steps += 1;
+
+        let text_end = TEXT_BASE + self.program.text_size;
+        if self.pc == text_end {
+            return Ok(Some(0));
+        }
+        if self.pc < TEXT_BASE || self.pc > text_end || !self.pc.is_multiple_of(COMPRESSED_LENGTH) {
+            return Err(self.fault(format!("jumped to {:#x}, outside of the program", self.pc)));
+        }
+        let index = (self.pc - TEXT_BASE) as usize;
+        let (inst, length) = match self.text[index / COMPRESSED_LENGTH as usize] {
+            Some(decoded) => decoded,
+            None => {
+                let half = u16::from_le_bytes([self.memory[index], self.memory[index + 1]]);
+                let message = match self.memory.get(index + 2..index + 4) {
+                    Some(high) if half & 0b11 == 0b11 => {
+                        let high = u16::from_le_bytes([high[0], high[1]]);
+                        format!(
+                            "illegal instruction {:#010x}",
+                            (high as u32) << 16 | half as u32
+                        )
+                    }
+                    _ => format!("illegal instruction {:#06x}", half),
+                };
+                return Err(self.fault(message));
+            }
+        };
+
+        let mut next_pc = self.pc.wrapping_add(length);
+        match inst {
+            Inst::Lui { rd, imm } => self.set_register(rd, (imm << 12) as u64),
+            Inst::Auipc { rd, imm } => {
+                self.set_register(rd, self.pc.wrapping_add((imm << 12) as u64))
+            }
+            Inst::Jal { rd, offset } => {
+                self.set_register(rd, next_pc);
+                next_pc = self.pc.wrapping_add(offset as u64);
+            }
+            Inst::Jalr { rd, rs1, offset } => {
+                let target = self.register(rs1).wrapping_add(offset as u64) & !1;
+                self.set_register(rd, next_pc);
+                next_pc = self.address(target);
+            }
+            Inst::Branch {
+                op,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let (a, b) = (self.register(rs1), self.register(rs2));
+                let taken = match op {
+                    BranchOp::Beq => a == b,
+                    BranchOp::Bne => a != b,
+                    BranchOp::Blt => (a as i64) < (b as i64),
+                    BranchOp::Bge => (a as i64) >= (b as i64),
+                    BranchOp::Bltu => a < b,
+                    BranchOp::Bgeu => a >= b,
+                };
+                if taken {
+                    next_pc = self.pc.wrapping_add(offset as u64);
+                }
+            }
+            Inst::Load {
+                width,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let address = self.address(self.register(rs1).wrapping_add(offset as u64));
+                let range = self.memory_range(address, width.bytes())?;
+                let mut bytes = [0; 8];
+                bytes[..range.len()].copy_from_slice(&self.memory[range]);
+                let value = u64::from_le_bytes(bytes);
+                let value = if width.is_signed() {
+                    let shift = 64 - 8 * width.bytes();
+                    (((value << shift) as i64) >> shift) as u64
+                } else {
+                    value
+                };
+                self.set_register(rd, value);
+            }
+            Inst::Store {
+                width,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let address = self.address(self.register(rs1).wrapping_add(offset as u64));
+                let range = self.memory_range(address, width.bytes())?;
+                let bytes = self.register(rs2).to_le_bytes();
+                let len = range.len();
+                self.memory[range].copy_from_slice(&bytes[..len]);
+            }
+            Inst::OpImm {
+                op,
+                rd,
+                rs1,
+                imm,
+                word,
+            } => {
+                let value = self.alu(op, self.register(rs1), imm as u64, word);
+                self.set_register(rd, value);
+            }
+            Inst::Op {
+                op,
+                rd,
+                rs1,
+                rs2,
+                word,
+            } => {
+                let value = self.alu(op, self.register(rs1), self.register(rs2), word);
+                self.set_register(rd, value);
+            }
+            Inst::Ecall => {
+                if let Some(code) = self.ecall(streams)? {
+                    return Ok(Some(code));
+                }
+            }
+        }
+
+        self.pc = next_pc;
+        Ok(None)
+    }
+
+    /// Perform the system call requested in a7, returning the exit code if the program exited
+    fn ecall(&mut self, streams: &mut Streams) -> Result<Option<i32>, SimError> {
+        let a0 = self.register(Register::A0);
+        let a1 = self.register(Register::A1);
+        let a2 = self.register(Register::A2);
+
+        let result = match self.register(Register::A7) {
+            SYS_READ if a0 == 0 => {
+                // Show any prompt before waiting for input
+                streams.stdout.flush()?;
+                match self.memory_range(self.address(a1), a2) {
+                    Ok(range) => read(streams.stdin, &mut self.memory[range])? as i64,
+                    Err(_) => EFAULT,
+                }
+            }
+            SYS_WRITE if a0 == 1 || a0 == 2 => {
+                let stream = if a0 == 1 {
+                    &mut *streams.stdout
+                } else {
+                    &mut *streams.stderr
+                };
+                match self.memory_range(self.address(a1), a2) {
+                    Ok(range) => {
+                        stream.write_all(&self.memory[range])?;
+                        a2 as i64
+                    }
+                    Err(_) => EFAULT,
+                }
+            }
+            SYS_READ | SYS_WRITE => EBADF,
+            SYS_EXIT => return Ok(Some(a0 as i32)),
+            RARS_EXIT => return Ok(Some(0)),
+            RARS_PRINT_CHAR => {
+                streams.stdout.write_all(&[a0 as u8])?;
+                a0 as i64
+            }
+            RARS_READ_CHAR => {
+                streams.stdout.flush()?;
+                let mut byte = [0];
+                match read(streams.stdin, &mut byte)? {
+                    0 => -1,
+                    _ => byte[0] as i64,
+                }
+            }
+            number => return Err(self.fault(format!("unsupported system call {}", number))),
+        };
+
+        self.set_register(Register::A0, result as u64);
+        Ok(None)
+    }
+
+    /// Compute the result of an arithmetic operation. Operations on RV32, and `*w` operations on
+    /// RV64, use the low 32 bits of their operands and sign-extend the result.
+    fn alu(&self, op: AluOp, a: u64, b: u64, word: bool) -> u64 {
+        if word || self.program.xlen == Xlen::Rv32 {
+            let (a, b) = (a as u32, b as u32);
+            let result = match op {
+                AluOp::Add => a.wrapping_add(b),
+                AluOp::Sub => a.wrapping_sub(b),
+                AluOp::Sll => a << (b & 31),
+                AluOp::Slt => ((a as i32) < (b as i32)) as u32,
+                AluOp::Sltu => (a < b) as u32,
+                AluOp::Xor => a ^ b,
+                AluOp::Srl => a >> (b & 31),
+                AluOp::Sra => ((a as i32) >> (b & 31)) as u32,
+                AluOp::Or => a | b,
+                AluOp::And => a & b,
+                AluOp::Mul => a.wrapping_mul(b),
+                AluOp::Mulh => ((a as i32 as i64 * b as i32 as i64) >> 32) as u32,
+                AluOp::Mulhsu => ((a as i32 as i64 * b as i64) >> 32) as u32,
+                AluOp::Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+                AluOp::Div if b == 0 => u32::MAX,
+                AluOp::Div => (a as i32).wrapping_div(b as i32) as u32,
+                AluOp::Divu if b == 0 => u32::MAX,
+                AluOp::Divu => a / b,
+                AluOp::Rem if b == 0 => a,
+                AluOp::Rem => (a as i32).wrapping_rem(b as i32) as u32,
+                AluOp::Remu if b == 0 => a,
+                AluOp::Remu => a % b,
+            };
+            result as i32 as i64 as u64
+        } else {
+            match op {
+                AluOp::Add => a.wrapping_add(b),
+                AluOp::Sub => a.wrapping_sub(b),
+                AluOp::Sll => a << (b & 63),
+                AluOp::Slt => ((a as i64) < (b as i64)) as u64,
+                AluOp::Sltu => (a < b) as u64,
+                AluOp::Xor => a ^ b,
+                AluOp::Srl => a >> (b & 63),
+                AluOp::Sra => ((a as i64) >> (b & 63)) as u64,
+                AluOp::Or => a | b,
+                AluOp::And => a & b,
+                AluOp::Mul => a.wrapping_mul(b),
+                AluOp::Mulh => ((a as i64 as i128 * b as i64 as i128) >> 64) as u64,
+                AluOp::Mulhsu => ((a as i64 as i128 * b as i128) >> 64) as u64,
+                AluOp::Mulhu => ((a as u128 * b as u128) >> 64) as u64,
+                AluOp::Div if b == 0 => u64::MAX,
+                AluOp::Div => (a as i64).wrapping_div(b as i64) as u64,
+                AluOp::Divu if b == 0 => u64::MAX,
+                AluOp::Divu => a / b,
+                AluOp::Rem if b == 0 => a,
+                AluOp::Rem => (a as i64).wrapping_rem(b as i64) as u64,
+                AluOp::Remu if b == 0 => a,
+                AluOp::Remu => a % b,
+            }
+        }
+    }
+
+    /// Set a register, keeping values sign-extended on RV32. Writes to `zero` are ignored.
+    fn set_register(&mut self, register: Register, value: u64) {
+        if register != Register::ZERO {
+            self.registers[register.0 as usize] = match self.program.xlen {
+                Xlen::Rv32 => value as i32 as i64 as u64,
+                Xlen::Rv64 => value,
+            };
+        }
+    }
+
+    /// Convert a register value to an address, which is only 32 bits on RV32
+    fn address(&self, value: u64) -> u64 {
+        match self.program.xlen {
+            Xlen::Rv32 => value & 0xffff_ffff,
+            Xlen::Rv64 => value,
+        }
+    }
+
+    /// The range of `memory` holding the `len` bytes starting at `address`
+    fn memory_range(&self, address: u64, len: u64) -> Result<std::ops::Range<usize>, SimError> {
+        let end = TEXT_BASE + self.memory.len() as u64;
+        match address.checked_add(len) {
+            Some(last) if address >= TEXT_BASE && last <= end => {
+                let start = (address - TEXT_BASE) as usize;
+                Ok(start..start + len as usize)
+            }
+            _ => Err(self.fault(format!(
+                "access to {} bytes at {:#x}, outside of memory",
+                len, address
+            ))),
+        }
+    }
+
+    /// Constructs an error for a fault at the current instruction
+    fn fault(&self, message: String) -> SimError {
+        SimError::Fault {
+            pc: self.pc,
+            message,
+        }
+    }
+}
+
+/// Read as many bytes as are available into `buffer`, retrying if interrupted
+fn read(stdin: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match stdin.read(buffer) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
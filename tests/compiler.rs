@@ -0,0 +1,428 @@
+use std::io::{self, Read, Write};
+
+use brainfuck_riscv::{
+    compiler::{self, OptionError},
+    dialect::{CellWidth, Dialect, EofBehavior},
+    difftest::DEFAULT_STEP_LIMIT,
+    optimizer::{OptLevel, PassManager},
+    parser::parse,
+    riscv_sim::{self, Machine, Output, Xlen},
+};
+
+/// Optimize a program and compile it for the target with the given target options
+fn compile(
+    source: &str,
+    level: OptLevel,
+    target: &str,
+    options: &[(&str, &str)],
+    dialect: Dialect,
+) -> String {
+    let mut backend = compiler::backend(target, dialect).expect("unknown target");
+    for (name, value) in options {
+        backend
+            .set_option(name, value)
+            .expect("invalid target option");
+    }
+    backend.validate().expect("unsupported target options");
+
+    let program = PassManager::new(level).run(&parse(source).unwrap());
+    compiler::compile(backend.as_mut(), &program)
+}
+
+/// Run compiled code in the emulator, failing if it does not exit within the default step limit
+fn run(assembly: &str, target: &str, input: &[u8]) -> Output {
+    let xlen = Xlen::for_target(target).unwrap();
+    let program = riscv_sim::assemble(assembly, xlen).expect("failed to assemble");
+    let mut machine = Machine::new(&program);
+    machine.step_limit = Some(DEFAULT_STEP_LIMIT);
+
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let exit_code = machine
+        .run(&mut &input[..], &mut stdout, &mut stderr)
+        .expect("failed to run");
+    Output {
+        stdout,
+        stderr,
+        exit_code,
+    }
+}
+
+#[test]
+fn clear_loop() {
+    // A clear loop is a single store, with no loop left to branch around
+    let assembly = compile("+++[-]", OptLevel::O1, "riscv32", &[], Dialect::default());
+    assert!(assembly.contains("sb zero, 0(s0)\n"), "{}", assembly);
+    assert!(!assembly.contains("start_"), "{}", assembly);
+
+    // Additions after the clear are folded into the value that it stores
+    let assembly = compile(
+        "+++[+]++.",
+        OptLevel::O1,
+        "riscv32",
+        &[],
+        Dialect::default(),
+    );
+    assert!(
+        assembly.contains("li s1, 2\nsb s1, 0(s0)\n"),
+        "{}",
+        assembly
+    );
+    assert_eq!(run(&assembly, "riscv32", b"").stdout, [2]);
+}
+
+#[test]
+fn multiply_loop() {
+    // Without the M extension, the product is built from shifts and additions
+    let source = "+++++[->+++++++++++>-------<<]>.>.";
+    for (arch, multiplies) in [("rv32im", true), ("rv32i", false)] {
+        let options = [("arch", arch)];
+        let assembly = compile(
+            source,
+            OptLevel::O2,
+            "riscv32",
+            &options,
+            Dialect::default(),
+        );
+        assert!(!assembly.contains("start_"), "{}", assembly);
+        assert_eq!(assembly.contains("mul "), multiplies, "{}", assembly);
+        assert_eq!(assembly.contains("slli t2, s1, 3\n"), !multiplies);
+        assert_eq!(run(&assembly, "riscv32", b"").stdout, [55, 221]);
+    }
+}
+
+#[test]
+fn scan_loop() {
+    // Set cells 3 to 40, then scan right from the unaligned cell 3 and back left again
+    let source = format!(
+        ">>>{}{}[>]{}.<[<]>.",
+        "+>".repeat(38),
+        "<".repeat(38),
+        "+".repeat(65)
+    );
+    for target in ["riscv32", "riscv64"] {
+        let assembly = compile(&source, OptLevel::O1, target, &[], Dialect::default());
+        assert!(!assembly.contains("start_"), "{}", assembly);
+
+        // RV64 tests a whole word of 8-bit cells at a time once the pointer is aligned
+        assert_eq!(assembly.contains("scan_word_"), target == "riscv64");
+        assert_eq!(run(&assembly, target, b"").stdout, [65, 1]);
+    }
+}
+
+#[test]
+fn deferred_pointer_moves() {
+    // Cells are addressed by their offset from the pointer, which only moves before the
+    // second write
+    let assembly = compile(
+        ">+>++<<+.>>.",
+        OptLevel::O2,
+        "riscv32",
+        &[],
+        Dialect::default(),
+    );
+    assert!(assembly.contains("lbu s1, 1(s0)\n"), "{}", assembly);
+    assert!(assembly.contains("lbu s1, 2(s0)\n"), "{}", assembly);
+    assert_eq!(assembly.matches("addi s0, s0,").count(), 1, "{}", assembly);
+    assert_eq!(run(&assembly, "riscv32", b"").stdout, [1, 2]);
+}
+
+#[test]
+fn backends() {
+    for target in compiler::TARGETS {
+        let backend = compiler::backend(target, Dialect::default()).unwrap();
+        assert_eq!(backend.name(), *target);
+    }
+    assert!(compiler::backend("x86_64", Dialect::default()).is_none());
+
+    let mut backend = compiler::backend("riscv32", Dialect::default()).unwrap();
+    assert_eq!(
+        backend.set_option("unroll", "true"),
+        Err(OptionError::Unknown("unroll".to_string()))
+    );
+    assert_eq!(
+        backend.set_option("bounds-check", "maybe"),
+        Err(OptionError::InvalidValue {
+            name: "bounds-check".to_string(),
+            value: "maybe".to_string()
+        })
+    );
+    assert_eq!(
+        backend
+            .set_option("arch", "rv64gc")
+            .unwrap_err()
+            .to_string(),
+        "the riscv32 target cannot generate rv64gc code"
+    );
+    assert_eq!(backend.set_option("runtime", "linux"), Ok(()));
+    assert_eq!(backend.validate(), Ok(()));
+
+    // Each option is valid, but not every combination is
+    let dialect = Dialect {
+        cell_width: CellWidth::Bits64,
+        ..Dialect::default()
+    };
+    assert_eq!(
+        compiler::backend("riscv32", dialect).unwrap().validate(),
+        Err(OptionError::Unsupported(
+            "64-bit cells require an RV64 target".to_string()
+        ))
+    );
+    assert!(compiler::backend("riscv64", dialect)
+        .unwrap()
+        .validate()
+        .is_ok());
+}
+
+#[test]
+fn linux_runtime() {
+    let options = [("runtime", "linux")];
+    let assembly = compile(
+        ",[.[-],]",
+        OptLevel::O2,
+        "riscv64",
+        &options,
+        Dialect::default(),
+    );
+    assert!(assembly.contains(".bss\n"), "{}", assembly);
+    assert!(
+        assembly.contains(".globl _start\n_start:\n"),
+        "{}",
+        assembly
+    );
+    for syscall in ["li a7, 63\n", "li a7, 64\n", "li a7, 93\n"] {
+        assert!(assembly.contains(syscall), "{}", assembly);
+    }
+    assert!(!assembly.contains("main:"), "{}", assembly);
+
+    let output = run(&assembly, "riscv64", b"echo\n");
+    assert_eq!(output.stdout, b"echo\n");
+    assert_eq!(output.exit_code, 0);
+}
+
+/// Records each write separately, as the system calls that made them did
+struct Writes(Vec<Vec<u8>>);
+
+impl Write for Writes {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.push(bytes.to_vec());
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Counts the reads from some input, as the system calls that made them did
+struct Reads<'a>(&'a [u8], usize);
+
+impl Read for Reads<'_> {
+    fn read(&mut self, bytes: &mut [u8]) -> io::Result<usize> {
+        self.1 += 1;
+        self.0.read(bytes)
+    }
+}
+
+#[test]
+fn buffered_io() {
+    // Print "ab", a newline, and "cd"
+    let source = format!("{}.+.>{}.<+.+.", "+".repeat(97), "+".repeat(10));
+    for (runtime, buffered, writes) in [
+        ("rars", "false", &[&b"a"[..], b"b", b"\n", b"c", b"d"][..]),
+        ("linux", "false", &[b"a", b"b", b"\n", b"c", b"d"]),
+        ("rars", "true", &[b"ab\n", b"cd"]),
+        ("linux", "true", &[b"ab\n", b"cd"]),
+    ] {
+        let options = [("runtime", runtime), ("buffered-io", buffered)];
+        let assembly = compile(
+            &source,
+            OptLevel::O2,
+            "riscv32",
+            &options,
+            Dialect::default(),
+        );
+        let program = riscv_sim::assemble(&assembly, Xlen::Rv32).unwrap();
+
+        // The buffer is written out after each newline and before exiting
+        let mut stdout = Writes(Vec::new());
+        let code = Machine::new(&program)
+            .run(&mut &b""[..], &mut stdout, &mut io::sink())
+            .unwrap();
+        assert_eq!(code, 0);
+        assert_eq!(
+            stdout.0, writes,
+            "{} runtime, buffered: {}",
+            runtime, buffered
+        );
+    }
+
+    // Input is read into the buffer all at once, followed by a read that finds its end
+    for (buffered, reads) in [("false", 6), ("true", 2)] {
+        let options = [("runtime", "linux"), ("buffered-io", buffered)];
+        let assembly = compile(
+            ",[.[-],]",
+            OptLevel::O2,
+            "riscv32",
+            &options,
+            Dialect::default(),
+        );
+        let program = riscv_sim::assemble(&assembly, Xlen::Rv32).unwrap();
+
+        let mut stdin = Reads(&b"input"[..], 0);
+        let mut stdout = Vec::new();
+        Machine::new(&program)
+            .run(&mut stdin, &mut stdout, &mut io::sink())
+            .unwrap();
+        assert_eq!(stdout, b"input");
+        assert_eq!(stdin.1, reads, "buffered: {}", buffered);
+    }
+}
+
+#[test]
+fn tape_layout() {
+    // Adding 256 only wraps 8-bit cells back to zero
+    let source = format!("{}[>+<[-]]>.", "+".repeat(256));
+    for (bits, load, store, output) in [
+        (8, "lbu", "sb", 0),
+        (16, "lhu", "sh", 1),
+        (32, "lw", "sw", 1),
+        (64, "ld", "sd", 1),
+    ] {
+        let dialect = Dialect {
+            tape_length: 10,
+            cell_width: CellWidth::from_bits(bits).unwrap(),
+            ..Dialect::default()
+        };
+        let assembly = compile(&source, OptLevel::O0, "riscv64", &[], dialect);
+        assert!(
+            assembly.contains(&format!("{} s1, 0(s0)\n", load)),
+            "{}",
+            assembly
+        );
+        assert!(
+            assembly.contains(&format!("{} s1, 0(s0)\n", store)),
+            "{}",
+            assembly
+        );
+        let space = format!("memory: .space {}\n", 10 * bits / 8);
+        assert!(assembly.contains(&space), "{}", assembly);
+        assert_eq!(
+            run(&assembly, "riscv64", b"").stdout,
+            [output],
+            "{} bits",
+            bits
+        );
+    }
+
+    // 32-bit cells are sign-extended when loaded on RV64, which must not change the result of
+    // storing them, adding to them, or testing them
+    let dialect = Dialect {
+        cell_width: CellWidth::Bits32,
+        ..Dialect::default()
+    };
+    for (source, level) in [
+        ("-.[+>+<]>.", OptLevel::O0),
+        ("-[->+<]>.+>+<[>-<[-]]>.", OptLevel::O2),
+    ] {
+        let assembly = compile(source, level, "riscv64", &[], dialect);
+        assert_eq!(
+            run(&assembly, "riscv64", b"").stdout,
+            [255, 1],
+            "{}",
+            source
+        );
+    }
+
+    // The pointer can start in the middle of the tape and move left
+    let dialect = Dialect {
+        tape_length: 10,
+        tape_start: 5,
+        ..Dialect::default()
+    };
+    let assembly = compile("<<<<<+.", OptLevel::O2, "riscv32", &[], dialect);
+    assert!(
+        assembly.contains("la s0, memory\naddi s0, s0, 5\n"),
+        "{}",
+        assembly
+    );
+    assert_eq!(run(&assembly, "riscv32", b"").stdout, [1]);
+}
+
+#[test]
+fn eof_behaviors() {
+    // Reading at the end of input sets the cell as the behavior chooses, or leaves it as the
+    // 1 that it starts with or the last byte that was read
+    for (eof, byte, after_input) in [
+        (EofBehavior::Unchanged, 1, b'b'),
+        (EofBehavior::Zero, 0, 0),
+        (EofBehavior::MinusOne, 255, 255),
+    ] {
+        let dialect = Dialect {
+            eof,
+            ..Dialect::default()
+        };
+        for options in [
+            &[("runtime", "rars")][..],
+            &[("runtime", "linux")],
+            &[("runtime", "linux"), ("buffered-io", "true")],
+        ] {
+            let assembly = compile("+,.,,.", OptLevel::O2, "riscv32", options, dialect);
+            let output = run(&assembly, "riscv32", b"");
+            assert_eq!(output.stdout, [byte, byte], "{:?} {:?}", eof, options);
+
+            let output = run(&assembly, "riscv32", b"ab");
+            assert_eq!(
+                output.stdout,
+                [b'a', after_input],
+                "{:?} {:?}",
+                eof,
+                options
+            );
+        }
+    }
+}
+
+#[test]
+fn bounds_check() {
+    let dialect = Dialect {
+        tape_length: 10,
+        ..Dialect::default()
+    };
+    let options = [("bounds-check", "true")];
+
+    // Unoptimized programs check each access after the pointer moves, so output written before
+    // it is kept, but optimized programs check every cell that a region accesses before it runs
+    for (source, level, code, message, stdout) in [
+        (
+            "+.<+",
+            OptLevel::O0,
+            3,
+            "underflow at instruction 3",
+            &[1][..],
+        ),
+        (
+            "+.>>>>>>>>>>+",
+            OptLevel::O2,
+            4,
+            "overflow at instruction 2",
+            &[],
+        ),
+        ("+[>+]", OptLevel::O2, 4, "overflow at instruction 2", &[]),
+        ("+[<]", OptLevel::O2, 3, "underflow at instruction 1", &[]),
+    ] {
+        for target in ["riscv32", "riscv64"] {
+            let assembly = compile(source, level, target, &options, dialect);
+            let output = run(&assembly, target, b"");
+            assert_eq!(output.exit_code, code, "{}", source);
+            let expected = format!("pointer {}\n", message);
+            assert_eq!(String::from_utf8_lossy(&output.stderr), expected);
+            assert_eq!(output.stdout, stdout, "{}", source);
+        }
+    }
+
+    // Moving the pointer off the tape is allowed as long as no cell is accessed there
+    let assembly = compile("<>+.", OptLevel::O0, "riscv32", &options, dialect);
+    let output = run(&assembly, "riscv32", b"");
+    assert_eq!((output.exit_code, output.stdout), (0, vec![1]));
+}
@@ -0,0 +1,2 @@
+Copy input to output and clear each cell before reading so that the end of input stops the loop
+,[.[-],]
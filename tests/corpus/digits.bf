@@ -0,0 +1,2 @@
+Print the digits 0 to 9 and a newline
+++++++++++>++++++[>++++++++<-]<[>>.+<<-]++++++++++.
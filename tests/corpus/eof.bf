@@ -0,0 +1,2 @@
+Read four times from two bytes of input so that the end of input is reached
+,,,,.
@@ -0,0 +1,2 @@
+Multiply 7 by 8 and print the result as a character
++++++++[>++++++++<-]>.
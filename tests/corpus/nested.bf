@@ -0,0 +1,4 @@
+Nested loops that print a 3 by 4 grid of stars then clear the star cell
+>>++++++[>+++++++<-]>>++++++++++<<<<
++++[>++++[>>.<<-]>>>.<<<<-]
+>>>[-]
@@ -0,0 +1,2 @@
+The pointer may step off the start of the tape if no cell is accessed there
+<>+.
@@ -0,0 +1,2 @@
+Shift each byte of input up by one
+,[+.[-],]
@@ -0,0 +1,2 @@
+Wrap below zero and back up to print 255 then 0 as bytes
+-.+.
@@ -0,0 +1,182 @@
+use brainfuck_riscv::{
+    diagnostics::{self, span_at, Diagnostic, Renderer},
+    parser::{parse, Span},
+};
+
+/// Render every parse error and lint warning for `source` without color
+fn render(source: &str) -> String {
+    let mut diagnostics = match parse(source) {
+        Ok(_) => Vec::new(),
+        Err(error) => diagnostics::from_parse_error(&error, source),
+    };
+    diagnostics.extend(diagnostics::lint(source));
+
+    let renderer = Renderer::new(false);
+    diagnostics
+        .iter()
+        .map(|diagnostic| renderer.render(diagnostic, "prog.bf", source))
+        .collect()
+}
+
+#[test]
+fn single_error() {
+    assert_eq!(
+        render("+[."),
+        "\
+error: unmatched `[`
+ --> prog.bf:1:2
+  |
+1 | +[.
+  |  ^ this loop is never closed
+  |    - expected a matching `]` before the end of the file
+"
+    );
+}
+
+#[test]
+fn multiple_errors() {
+    // A `]` with no `[` anywhere before it gets a note rather than a label
+    assert_eq!(
+        render("]+\n[[-]\n"),
+        "\
+error: unmatched `]`
+ --> prog.bf:1:1
+  |
+1 | ]+
+  | ^ this `]` has no matching `[`
+  = note: no `[` opens a loop before it
+error: unmatched `[`
+ --> prog.bf:2:1
+  |
+2 | [[-]
+  | ^ this loop is never closed
+  |     - expected a matching `]` before the end of the file
+"
+    );
+}
+
+#[test]
+fn multi_line_source() {
+    assert_eq!(
+        render("a\nb\nc\n[\n.\nd\n]]"),
+        "\
+error: unmatched `]`
+ --> prog.bf:7:2
+  |
+4 | [
+  | - the nearest `[` is already closed before this point
+...
+7 | ]]
+  |  ^ this `]` has no matching `[`
+"
+    );
+}
+
+#[test]
+fn tab_indented_line() {
+    // The markers are indented with the same tabs as the line, so they line up with it
+    assert_eq!(
+        render("\t\t[]]"),
+        "\
+error: unmatched `]`
+ --> prog.bf:1:5
+  |
+1 | \t\t[]]
+  | \t\t- the nearest `[` is already closed before this point
+  | \t\t  ^ this `]` has no matching `[`
+warning: empty loop
+ --> prog.bf:1:3
+  |
+1 | \t\t[]]
+  | \t\t^ this loop never terminates if the current cell is nonzero
+  | \t\t - the loop ends here
+"
+    );
+}
+
+#[test]
+fn non_ascii_before_span() {
+    // Markers are indented by characters rather than bytes
+    assert_eq!(
+        render("é → ["),
+        "\
+error: unmatched `[`
+ --> prog.bf:1:5
+  |
+1 | é → [
+  |     ^ this loop is never closed
+  |      - expected a matching `]` before the end of the file
+"
+    );
+}
+
+#[test]
+fn empty_loop_warning() {
+    assert_eq!(
+        render("+[]-"),
+        "\
+warning: empty loop
+ --> prog.bf:1:2
+  |
+1 | +[]-
+  |  ^ this loop never terminates if the current cell is nonzero
+  |   - the loop ends here
+"
+    );
+
+    // Comments between the brackets still leave the loop empty, but commands do not
+    assert_eq!(diagnostics::lint("[ comment ]").len(), 1);
+    assert!(diagnostics::lint("[-]").is_empty());
+}
+
+#[test]
+fn unlabeled_diagnostics() {
+    let renderer = Renderer::new(false);
+    assert_eq!(
+        renderer.render(&Diagnostic::error("no input file"), "prog.bf", ""),
+        "error: no input file\n"
+    );
+    assert_eq!(
+        renderer.render(
+            &Diagnostic::warning("unused option").with_note("it has no effect"),
+            "prog.bf",
+            ""
+        ),
+        "warning: unused option\n = note: it has no effect\n"
+    );
+}
+
+#[test]
+fn spans() {
+    let source = "ab\né→[";
+    assert_eq!(
+        span_at(source, 0),
+        Span {
+            offset: 0,
+            len: 1,
+            line: 1,
+            column: 1
+        }
+    );
+    assert_eq!(
+        span_at(source, 5),
+        Span {
+            offset: 5,
+            len: 3,
+            line: 2,
+            column: 2
+        }
+    );
+    assert_eq!(
+        span_at(source, 8),
+        Span {
+            offset: 8,
+            len: 1,
+            line: 2,
+            column: 3
+        }
+    );
+
+    // The end of the source has no character
+    assert_eq!(span_at(source, source.len()).len, 0);
+}
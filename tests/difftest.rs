@@ -0,0 +1,317 @@
+use std::path::Path;
+
+use brainfuck_riscv::{
+    compiler,
+    dialect::{CellWidth, Dialect, EofBehavior},
+    difftest::{self, Case, Harness},
+    generator::{self, Config},
+    instruction::Instruction,
+    optimizer::{OptLevel, PassManager},
+    parser::parse,
+};
+
+/// Run every program in the corpus through the harness, with the given target and target options
+fn check_corpus(target: &str, options: &[(&str, &str)], level: OptLevel, dialect: Dialect) {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let cases = difftest::load_corpus(&corpus).expect("failed to load corpus");
+    assert!(!cases.is_empty(), "corpus is empty");
+
+    let mut backend = compiler::backend(target, dialect).expect("unknown target");
+    for (name, value) in options {
+        backend
+            .set_option(name, value)
+            .expect("invalid target option");
+    }
+    backend.validate().expect("unsupported target options");
+
+    let mut harness = Harness::new(dialect, PassManager::new(level), backend);
+    let failures: Vec<String> = cases
+        .iter()
+        .filter_map(|case| harness.check(case).err())
+        .map(|failure| failure.to_string())
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn riscv32() {
+    check_corpus("riscv32", &[], OptLevel::O2, Dialect::default());
+}
+
+#[test]
+fn riscv64() {
+    check_corpus("riscv64", &[], OptLevel::O2, Dialect::default());
+}
+
+#[test]
+fn unoptimized() {
+    check_corpus("riscv32", &[], OptLevel::O0, Dialect::default());
+    check_corpus("riscv64", &[], OptLevel::O1, Dialect::default());
+}
+
+#[test]
+fn linux_runtime() {
+    check_corpus(
+        "riscv64",
+        &[("runtime", "linux")],
+        OptLevel::O2,
+        Dialect::default(),
+    );
+}
+
+#[test]
+fn buffered_io() {
+    for runtime in ["rars", "linux"] {
+        let options = [("runtime", runtime), ("buffered-io", "true")];
+        check_corpus("riscv32", &options, OptLevel::O2, Dialect::default());
+    }
+}
+
+#[test]
+fn arches() {
+    // The linux runtime records the ISA, so the emulator's assembler rejects any instruction
+    // outside of it
+    for (target, arch) in [
+        ("riscv32", "rv32i"),
+        ("riscv32", "rv32im"),
+        ("riscv64", "rv64i"),
+        ("riscv64", "rv64gc"),
+    ] {
+        let options = [("runtime", "linux"), ("arch", arch)];
+        check_corpus(target, &options, OptLevel::O2, Dialect::default());
+    }
+    check_corpus(
+        "riscv32",
+        &[("arch", "rv32i")],
+        OptLevel::O2,
+        Dialect::default(),
+    );
+}
+
+#[test]
+fn compressed() {
+    // Wider cells use loads and stores that have compressed forms
+    for bits in [8, 32, 64] {
+        let dialect = Dialect {
+            cell_width: CellWidth::from_bits(bits).unwrap(),
+            ..Dialect::default()
+        };
+        let options = [("runtime", "linux"), ("compressed", "true")];
+        check_corpus("riscv64", &options, OptLevel::O2, dialect);
+    }
+}
+
+#[test]
+fn bounds_check() {
+    // Unoptimized programs move the pointer more, including off the tape
+    for level in [OptLevel::O0, OptLevel::O2] {
+        check_corpus(
+            "riscv64",
+            &[("bounds-check", "true")],
+            level,
+            Dialect::default(),
+        );
+    }
+}
+
+#[test]
+fn cell_widths() {
+    for bits in [16, 32, 64] {
+        let dialect = Dialect {
+            cell_width: CellWidth::from_bits(bits).unwrap(),
+            ..Dialect::default()
+        };
+        check_corpus("riscv64", &[], OptLevel::O2, dialect);
+    }
+}
+
+#[test]
+fn eof_behaviors() {
+    let dialect = Dialect {
+        eof: EofBehavior::Zero,
+        ..Dialect::default()
+    };
+    check_corpus("riscv32", &[], OptLevel::O2, dialect);
+    check_corpus("riscv32", &[("runtime", "linux")], OptLevel::O2, dialect);
+}
+
+#[test]
+fn eof_minus_one() {
+    // Most of the corpus relies on the end of input leaving a zero cell, so only check the
+    // program that reads past the end of its input
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let case = difftest::load_corpus(&corpus)
+        .expect("failed to load corpus")
+        .into_iter()
+        .find(|case| case.name == "eof")
+        .expect("missing eof case");
+
+    let dialect = Dialect {
+        eof: EofBehavior::MinusOne,
+        ..Dialect::default()
+    };
+    for runtime in ["rars", "linux"] {
+        let mut backend = compiler::backend("riscv32", dialect).unwrap();
+        backend.set_option("runtime", runtime).unwrap();
+        let mut harness = Harness::new(dialect, PassManager::new(OptLevel::O2), backend);
+        harness.check(&case).unwrap();
+    }
+}
+
+#[test]
+fn tape_start() {
+    let dialect = Dialect {
+        tape_length: 100,
+        tape_start: 50,
+        ..Dialect::default()
+    };
+    check_corpus(
+        "riscv32",
+        &[("bounds-check", "true")],
+        OptLevel::O2,
+        dialect,
+    );
+}
+
+#[test]
+fn cached_cell() {
+    for bits in [8, 16, 32, 64] {
+        let dialect = Dialect {
+            cell_width: CellWidth::from_bits(bits).unwrap(),
+            ..Dialect::default()
+        };
+        let options = [("runtime", "linux"), ("cache-cell", "true")];
+        check_corpus("riscv64", &options, OptLevel::O2, dialect);
+    }
+    for options in [
+        &[("cache-cell", "true")][..],
+        &[("cache-cell", "true"), ("buffered-io", "true")],
+        &[("cache-cell", "true"), ("bounds-check", "true")],
+    ] {
+        check_corpus("riscv32", options, OptLevel::O2, Dialect::default());
+        check_corpus("riscv32", options, OptLevel::O0, Dialect::default());
+    }
+
+    let dialect = Dialect {
+        cell_width: CellWidth::Bits16,
+        eof: EofBehavior::Zero,
+        ..Dialect::default()
+    };
+    let mut backend = compiler::backend("riscv32", dialect).unwrap();
+    backend.set_option("cache-cell", "true").unwrap();
+    let mut harness = Harness::new(dialect, PassManager::new(OptLevel::O2), backend);
+    for seed in 0..200 {
+        let case = Case {
+            name: format!("seed {}", seed),
+            source: generator::generate(seed, Config::default()),
+            input: b"generated".to_vec(),
+        };
+        if let Err(failure) = harness.check(&case) {
+            panic!("{}\n{}", failure, case.source);
+        }
+    }
+}
+
+#[test]
+fn far_loops() {
+    // A loop whose body is too large for a single branch, around a loop that is not
+    let source = format!("+++[>{}[-.]<-]", ",.".repeat(300));
+    let case = Case {
+        name: "far".to_string(),
+        source: source.clone(),
+        input: (0..900).map(|byte| (byte % 7) as u8).collect(),
+    };
+    for (target, runtime) in [("riscv32", "rars"), ("riscv64", "linux")] {
+        let dialect = Dialect::default();
+        let mut backend = compiler::backend(target, dialect).unwrap();
+        backend.set_option("runtime", runtime).unwrap();
+        let mut harness = Harness::new(dialect, PassManager::new(OptLevel::O2), backend);
+        harness.check(&case).unwrap();
+    }
+
+    // Only the outer loop jumps over its branches
+    let program = PassManager::new(OptLevel::O2).run(&parse(&source).unwrap());
+    let mut backend = compiler::backend("riscv64", Dialect::default()).unwrap();
+    let assembly = compiler::compile(backend.as_mut(), &program);
+    assert_eq!(assembly.matches("\nj start_").count(), 1);
+    assert_eq!(assembly.matches("\nj end_").count(), 1);
+    assert_eq!(assembly.matches("\nbnez s1, start_").count(), 2);
+    assert_eq!(assembly.matches("\nbeqz s1, end_").count(), 2);
+}
+
+#[test]
+fn large_offsets() {
+    // Pointer moves and cell offsets beyond the 12-bit immediates of `addi`, loads, and stores
+    let cases = [
+        format!("{}+++.", ">".repeat(3000)),
+        format!(
+            "+++[-{}++{}]{}.",
+            ">".repeat(2500),
+            "<".repeat(2500),
+            ">".repeat(2500)
+        ),
+    ];
+    let program = PassManager::new(OptLevel::O2).run(&parse(&cases[1]).unwrap());
+    assert!(program.contains(&Instruction::MulAdd {
+        offset: 2500,
+        factor: 2
+    }));
+
+    for (target, bits) in [("riscv32", 8), ("riscv64", 8), ("riscv64", 64)] {
+        let dialect = Dialect {
+            cell_width: CellWidth::from_bits(bits).unwrap(),
+            ..Dialect::default()
+        };
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let backend = compiler::backend(target, dialect).unwrap();
+            let mut harness = Harness::new(dialect, PassManager::new(level), backend);
+            for source in &cases {
+                let case = Case {
+                    name: "large offset".to_string(),
+                    source: source.clone(),
+                    input: Vec::new(),
+                };
+                harness.check(&case).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn generated_loop_kinds() {
+    // The generator must produce the loops that the optimizer replaces, including the
+    // single-step scans that RV64 searches a word at a time
+    let programs: Vec<Vec<Instruction>> = (0..50)
+        .map(|seed| {
+            let source = generator::generate(seed, Config::default());
+            PassManager::new(OptLevel::O2).run(&parse(&source).unwrap())
+        })
+        .collect();
+    let found = |kind: fn(&Instruction) -> bool| programs.iter().flatten().any(kind);
+    assert!(found(|inst| *inst == Instruction::Scan { stride: 1 }));
+    assert!(found(
+        |inst| matches!(inst, Instruction::Scan { stride } if *stride < 0)
+    ));
+    assert!(found(|inst| matches!(inst, Instruction::SetByte { .. })));
+    assert!(found(|inst| matches!(inst, Instruction::MulAdd { .. })));
+}
+
+#[test]
+fn generated_programs() {
+    for target in ["riscv32", "riscv64"] {
+        let dialect = Dialect::default();
+        let backend = compiler::backend(target, dialect).unwrap();
+        let mut harness = Harness::new(dialect, PassManager::new(OptLevel::O2), backend);
+
+        for seed in 0..200 {
+            let case = Case {
+                name: format!("seed {}", seed),
+                source: generator::generate(seed, Config::default()),
+                input: b"generated".to_vec(),
+            };
+            if let Err(failure) = harness.check(&case) {
+                panic!("{}\n{}", failure, case.source);
+            }
+        }
+    }
+}
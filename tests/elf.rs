@@ -0,0 +1,349 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    path::Path,
+};
+
+use brainfuck_riscv::{
+    compiler::{
+        self,
+        riscv::{OVERFLOW_EXIT_CODE, UNDERFLOW_EXIT_CODE},
+    },
+    dialect::Dialect,
+    difftest,
+    elf::{self, FloatAbi},
+    interpreter,
+    isa::{Arch, Xlen},
+    optimizer::{OptLevel, PassManager},
+    parser::parse,
+    riscv_sim::{self, assembler::TEXT_BASE, Machine, Program},
+};
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Load an executable the way Linux does, by copying each loadable segment into memory and
+/// zeroing the rest of it
+fn load(file: &[u8]) -> Program {
+    assert_eq!(&file[..4], b"\x7fELF");
+    assert_eq!(file[4], 2, "not ELF64");
+    assert_eq!(u16_at(file, 16), 2, "not an executable");
+    assert_eq!(u16_at(file, 18), 243, "not RISC-V");
+
+    let entry = u64_at(file, 24);
+    let program_headers = u64_at(file, 32) as usize;
+    let count = u16_at(file, 56) as usize;
+
+    let mut image = Vec::new();
+    let mut text_size = 0;
+    for index in 0..count {
+        let header = program_headers + index * 56;
+        assert_eq!(u32_at(file, header), 1, "not a loadable segment");
+        let flags = u32_at(file, header + 4);
+        let offset = u64_at(file, header + 8) as usize;
+        let address = u64_at(file, header + 16);
+        let file_size = u64_at(file, header + 32) as usize;
+        let memory_size = u64_at(file, header + 40) as usize;
+        assert_eq!(offset as u64 % 0x1000, address % 0x1000);
+        assert!(file_size <= memory_size);
+
+        let start = (address - TEXT_BASE) as usize;
+        image.resize(image.len().max(start + memory_size), 0);
+        image[start..start + file_size].copy_from_slice(&file[offset..offset + file_size]);
+        if flags & 1 != 0 {
+            assert_eq!(address, TEXT_BASE, "unexpected executable segment");
+            text_size = file_size as u64;
+        }
+    }
+
+    let end = TEXT_BASE + image.len() as u64;
+    Program {
+        xlen: Xlen::Rv64,
+        text_size,
+        data_base: end,
+        data_size: 0,
+        bss_base: end,
+        bss_size: 0,
+        image,
+        entry,
+        compressed: 0,
+        symbols: HashMap::new(),
+        globals: HashSet::new(),
+    }
+}
+
+/// Compile a program to an executable with the given target options
+fn executable(source: &str, options: &[(&str, &str)]) -> Vec<u8> {
+    let dialect = Dialect::default();
+    let mut backend = compiler::backend("riscv64", dialect).unwrap();
+    backend.set_option("runtime", "linux").unwrap();
+    for (name, value) in options {
+        backend.set_option(name, value).unwrap();
+    }
+
+    let program = PassManager::new(OptLevel::O2).run(&parse(source).unwrap());
+    let assembly = compiler::compile(backend.as_mut(), &program);
+    elf::executable(&riscv_sim::assemble(&assembly, Xlen::Rv64).unwrap())
+}
+
+#[test]
+fn corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    for options in [
+        &[][..],
+        &[("buffered-io", "true"), ("bounds-check", "true")],
+        &[("compressed", "true"), ("bounds-check", "true")],
+    ] {
+        for case in difftest::load_corpus(&corpus).unwrap() {
+            let expected = interpreter::run(
+                &parse(&case.source).unwrap(),
+                Dialect::default(),
+                &case.input,
+            )
+            .unwrap();
+
+            let program = load(&executable(&case.source, options));
+            let mut output = Vec::new();
+            let code = Machine::new(&program)
+                .run(&mut &case.input[..], &mut output, &mut Vec::new())
+                .unwrap();
+            assert_eq!(code, 0, "{}", case.name);
+            assert_eq!(output, expected, "{}", case.name);
+        }
+    }
+}
+
+#[test]
+fn tape_is_not_stored() {
+    // The tape is 30000 cells, so it must be in the bss section rather than the file
+    let file = executable("+[.-]", &[]);
+    assert!(file.len() < 30000, "file is {} bytes", file.len());
+    assert!(load(&file).image.len() > 30000);
+}
+
+#[test]
+fn compressed_flag() {
+    // Only executables with compressed instructions are marked as needing the C extension
+    let source = "++++++++[>++++[>++>+++<<-]>-]>.>+.";
+    let normal = executable(source, &[]);
+    let compressed = executable(source, &[("compressed", "true")]);
+    assert_eq!(u32_at(&normal, 48) & 1, 0);
+    assert_eq!(u32_at(&compressed, 48) & 1, 1);
+    assert!(load(&compressed).text_size < load(&normal).text_size);
+}
+
+/// The number of cells on the tape passed to `bf_main`
+const LIBRARY_TAPE_LENGTH: usize = 30000;
+
+/// Compile a program with the library runtime
+fn library(source: &str, target: &str, options: &[(&str, &str)]) -> String {
+    let mut backend = compiler::backend(target, Dialect::default()).unwrap();
+    backend.set_option("runtime", "library").unwrap();
+    for (name, value) in options {
+        backend.set_option(name, value).unwrap();
+    }
+    backend.validate().unwrap();
+
+    let program = PassManager::new(OptLevel::O2).run(&parse(source).unwrap());
+    compiler::compile(backend.as_mut(), &program)
+}
+
+/// Generate code that overwrites every register that a function may change without restoring
+fn clobber() -> String {
+    [
+        "t0", "t1", "t2", "t3", "t4", "t5", "t6", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+    ]
+    .iter()
+    .map(|register| format!("li {}, -1\n", register))
+    .collect()
+}
+
+/// Assemble a program compiled with the library runtime along with a caller, which passes it a
+/// tape of `length` cells and callbacks that use the standard streams. The caller exits with
+/// the value returned by `bf_main`, or 100 if it did not preserve the registers that it must.
+fn link(library: &str, xlen: Xlen, length: usize) -> Program {
+    let (load, store, size) = match xlen {
+        Xlen::Rv32 => ("lw", "sw", 4),
+        Xlen::Rv64 => ("ld", "sd", 8),
+    };
+
+    let mut caller = String::new();
+    caller.push_str(".text\n.globl _start\n_start:\n");
+    for register in 0..12 {
+        caller.push_str(&format!("li s{}, {}\n", register, 1000 + register));
+    }
+    caller.push_str(&format!("la t0, saved_sp\n{} sp, (t0)\n", store));
+    caller.push_str("la t0, io\n");
+    for (index, label) in ["read", "write", "context"].iter().enumerate() {
+        caller.push_str(&format!(
+            "la t1, {}\n{} t1, {}(t0)\n",
+            label,
+            store,
+            index * size
+        ));
+    }
+    caller.push_str(&clobber());
+    caller.push_str(&format!("la a0, tape\nli a1, {}\nla a2, io\n", length));
+    caller.push_str("call bf_main\n");
+    for register in 0..12 {
+        caller.push_str(&format!(
+            "li t0, {}\nbne s{}, t0, clobbered\n",
+            1000 + register,
+            register
+        ));
+    }
+    caller.push_str(&format!(
+        "la t0, saved_sp\n{} t0, (t0)\nbne sp, t0, clobbered\n",
+        load
+    ));
+    caller.push_str("li a7, 93\necall\n");
+    caller.push_str("clobbered:\nli a0, 100\nli a7, 93\necall\n");
+
+    // Each callback checks its context, then reads or writes a byte through the stack
+    caller.push_str("read:\nla t0, context\nbne a0, t0, clobbered\naddi sp, sp, -16\n");
+    caller.push_str("li a0, 0\nmv a1, sp\nli a2, 1\nli a7, 63\necall\n");
+    caller.push_str("lbu t0, (sp)\naddi sp, sp, 16\nbgtz a0, read_byte\nli t0, -1\n");
+    caller.push_str("read_byte:\nmv a0, t0\n");
+    caller.push_str(&clobber());
+    caller.push_str("ret\n");
+    caller.push_str("write:\nla t0, context\nbne a0, t0, clobbered\naddi sp, sp, -16\n");
+    caller.push_str("sb a1, (sp)\nli a0, 1\nmv a1, sp\nli a2, 1\nli a7, 64\necall\n");
+    caller.push_str("addi sp, sp, 16\n");
+    caller.push_str(&clobber());
+    caller.push_str("li a0, -1\nret\n");
+
+    caller.push_str(&format!(".bss\n.align 3\nio: .space {}\n", 3 * size));
+    caller.push_str(&format!("saved_sp: .space {}\n", size));
+    caller.push_str(&format!("context: .space 1\ntape: .space {}\n", length));
+
+    riscv_sim::assemble(&format!("{}\n{}", caller, library), xlen).unwrap()
+}
+
+/// Run a linked program, returning its exit code and output
+fn call(program: &Program, input: &[u8]) -> (i32, Vec<u8>) {
+    let mut output = Vec::new();
+    let code = Machine::new(program)
+        .run(&mut &input[..], &mut output, &mut Vec::new())
+        .unwrap();
+    (code, output)
+}
+
+#[test]
+fn library_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    for (target, xlen) in [("riscv32", Xlen::Rv32), ("riscv64", Xlen::Rv64)] {
+        for options in [
+            &[][..],
+            &[("bounds-check", "true")],
+            &[("cache-cell", "true")],
+        ] {
+            for case in difftest::load_corpus(&corpus).unwrap() {
+                let expected = interpreter::run(
+                    &parse(&case.source).unwrap(),
+                    Dialect::default(),
+                    &case.input,
+                )
+                .unwrap();
+
+                let assembly = library(&case.source, target, options);
+                let program = link(&assembly, xlen, LIBRARY_TAPE_LENGTH);
+                let (code, output) = call(&program, &case.input);
+                assert_eq!(code, 0, "{} on {}", case.name, target);
+                assert_eq!(output, expected, "{} on {}", case.name, target);
+            }
+        }
+    }
+}
+
+#[test]
+fn library_bounds_errors() {
+    // The tape is only as long as the caller says, and leaving it returns an error code
+    // without writing anything
+    let options = [("bounds-check", "true")];
+    for (target, xlen) in [("riscv32", Xlen::Rv32), ("riscv64", Xlen::Rv64)] {
+        let cases = [
+            (">>>+.", 0, vec![1]),
+            (">>>>+.", OVERFLOW_EXIT_CODE, vec![]),
+            ("<+.", UNDERFLOW_EXIT_CODE, vec![]),
+        ];
+        for (source, expected_code, expected_output) in cases {
+            let program = link(&library(source, target, &options), xlen, 4);
+            assert_eq!(
+                call(&program, b""),
+                (expected_code, expected_output),
+                "`{}` on {}",
+                source,
+                target
+            );
+        }
+    }
+}
+
+#[test]
+fn object_file() {
+    for (target, xlen) in [("riscv32", Xlen::Rv32), ("riscv64", Xlen::Rv64)] {
+        let program = riscv_sim::assemble(&library(",[.,]", target, &[]), xlen).unwrap();
+        let file = elf::relocatable(&program, FloatAbi::default_for(Arch::default_for(xlen)));
+        assert_eq!(&file[..4], b"\x7fELF");
+        assert_eq!(u16_at(&file, 16), 1, "not a relocatable file");
+        assert_eq!(u16_at(&file, 18), 243, "not RISC-V");
+
+        // The section headers, which are at a different offset in ELF32 and ELF64
+        let (class, header_offset, count, header_size, symbol_size) = match xlen {
+            Xlen::Rv32 => (1, u32_at(&file, 32) as usize, u16_at(&file, 48), 40, 16),
+            Xlen::Rv64 => (2, u64_at(&file, 40) as usize, u16_at(&file, 60), 64, 24),
+        };
+        assert_eq!(file[4], class);
+        let field = |section: usize, index: usize| {
+            let offset = header_offset + section * header_size + 8 + index * (header_size - 16) / 6;
+            match xlen {
+                Xlen::Rv32 => u32_at(&file, offset) as usize,
+                Xlen::Rv64 => u64_at(&file, offset) as usize,
+            }
+        };
+        let kind = |section: usize| u32_at(&file, header_offset + section * header_size + 4);
+
+        // The text section holds the code unchanged, and the symbol table exports bf_main
+        let text = (0..count as usize)
+            .find(|section| kind(*section) == 1)
+            .unwrap();
+        let (offset, size) = (field(text, 2), field(text, 3));
+        assert_eq!(&file[offset..offset + size], &program.image[..size]);
+
+        let symtab = (0..count as usize)
+            .find(|section| kind(*section) == 2)
+            .unwrap();
+        let (offset, size) = (field(symtab, 2), field(symtab, 3));
+        let strtab = header_offset + (symtab + 1) * header_size;
+        let names = match xlen {
+            Xlen::Rv32 => u32_at(&file, strtab + 16) as usize,
+            Xlen::Rv64 => u64_at(&file, strtab + 24) as usize,
+        };
+        let exported: Vec<(String, u8, u64)> = file[offset..offset + size]
+            .chunks_exact(symbol_size)
+            .filter_map(|symbol| {
+                let (info, size) = if xlen == Xlen::Rv32 {
+                    (symbol[12], u64::from(u32_at(symbol, 8)))
+                } else {
+                    (symbol[4], u64_at(symbol, 16))
+                };
+                let name = u32_at(symbol, 0) as usize;
+                let end = file[names + name..].iter().position(|byte| *byte == 0)?;
+                let name = String::from_utf8(file[names + name..names + name + end].to_vec());
+                Some((name.ok()?, info, size)).filter(|_| info >> 4 == 1)
+            })
+            .collect();
+
+        // The function covers all of the text section, including its error handlers
+        assert_eq!(exported, [("bf_main".to_string(), 0x12, program.text_size)]);
+    }
+}
@@ -0,0 +1,490 @@
+use std::{collections::HashMap, convert::TryInto};
+
+use brainfuck_riscv::{
+    isa::{self, Arch, BranchOp, EncodeError, Inst, Pending, Register, Xlen},
+    riscv_sim::{self, assembler::TEXT_BASE},
+};
+
+/// Instructions and their encodings, as produced by GNU `as` and LLVM
+const RV32: &[(&str, u32)] = &[
+    ("lui a0, 0x12345", 0x1234_5537),
+    ("lui t0, 0xfffff", 0xffff_f2b7),
+    ("auipc s1, 0x80000", 0x8000_0497),
+    ("jalr ra, 12(t1)", 0x00c3_00e7),
+    ("lb a0, -1(s0)", 0xfff4_0503),
+    ("lhu t2, 2047(sp)", 0x7ff1_5383),
+    ("lw a1, -2048(a2)", 0x8006_2583),
+    ("sb a0, -1(s0)", 0xfea4_0fa3),
+    ("sh t2, 2047(sp)", 0x7e71_1fa3),
+    ("sw a1, -2048(a2)", 0x80b6_2023),
+    ("addi a0, zero, 1", 0x0010_0513),
+    ("addi sp, sp, -16", 0xff01_0113),
+    ("slti a0, a1, -5", 0xffb5_a513),
+    ("xori a0, a0, -1", 0xfff5_4513),
+    ("andi t0, t0, 255", 0x0ff2_f293),
+    ("slli a0, a0, 31", 0x01f5_1513),
+    ("srli a0, a1, 3", 0x0035_d513),
+    ("srai t0, t1, 31", 0x41f3_5293),
+    ("add s0, s0, t0", 0x0054_0433),
+    ("sub a0, zero, a1", 0x40b0_0533),
+    ("sra a2, a3, a4", 0x40e6_d633),
+    ("sltu a0, zero, a1", 0x00b0_3533),
+    ("mul a0, a1, a2", 0x02c5_8533),
+    ("mulhsu a0, a1, a2", 0x02c5_a533),
+    ("divu t0, t1, t2", 0x0273_52b3),
+    ("remu s2, s3, s4", 0x0349_f933),
+    ("ecall", 0x0000_0073),
+];
+
+const RV64: &[(&str, u32)] = &[
+    ("ld ra, 8(sp)", 0x0081_3083),
+    ("sd ra, -8(sp)", 0xfe11_3c23),
+    ("lwu a0, 4(a1)", 0x0045_e503),
+    ("addiw a0, a0, 1", 0x0015_051b),
+    ("slli a0, a0, 63", 0x03f5_1513),
+    ("srai a0, a0, 40", 0x4285_5513),
+    ("slliw a0, a0, 31", 0x01f5_151b),
+    ("sraiw t0, t1, 1", 0x4013_529b),
+    ("addw a0, a1, a2", 0x00c5_853b),
+    ("subw a0, a1, a2", 0x40c5_853b),
+    ("mulw a0, a1, a2", 0x02c5_853b),
+    ("remuw a0, a1, a2", 0x02c5_f53b),
+    ("sraw a0, a1, a2", 0x40c5_d53b),
+];
+
+/// Instructions and their compressed encodings on RV64, as produced by LLVM
+const COMPRESSED_RV64: &[(&str, u16)] = &[
+    ("addi s0, sp, 16", 0x0800),
+    ("addi a5, sp, 1020", 0x1ffc),
+    ("lw a0, 4(s1)", 0x40c8),
+    ("lw s0, 124(a5)", 0x5fe0),
+    ("ld a0, 8(s1)", 0x6488),
+    ("ld s1, 248(s0)", 0x7c64),
+    ("sw a0, 4(s1)", 0xc0c8),
+    ("sd s1, 248(s0)", 0xfc64),
+    ("nop", 0x0001),
+    ("addi s0, s0, -32", 0x1401),
+    ("addi t0, t0, 31", 0x02fd),
+    ("addiw a0, a0, -1", 0x357d),
+    ("sext.w t1, t1", 0x2301),
+    ("li t0, -1", 0x52fd),
+    ("li a0, 31", 0x457d),
+    ("addi sp, sp, -512", 0x7101),
+    ("addi sp, sp, 496", 0x617d),
+    ("lui a0, 1", 0x6505),
+    ("lui t0, 0xfffe0", 0x7281),
+    ("srli s0, s0, 1", 0x8005),
+    ("srli a5, a5, 63", 0x93fd),
+    ("srai s1, s1, 7", 0x849d),
+    ("andi s1, s1, -1", 0x98fd),
+    ("andi a0, a0, 31", 0x897d),
+    ("sub s0, s0, s1", 0x8c05),
+    ("xor a0, a0, a1", 0x8d2d),
+    ("or a2, a2, a3", 0x8e55),
+    ("and a4, a4, a5", 0x8f7d),
+    ("subw s0, s0, a5", 0x9c1d),
+    ("addw a0, a0, s1", 0x9d25),
+    ("slli t0, t0, 1", 0x0286),
+    ("slli s1, s1, 63", 0x14fe),
+    ("lw ra, 252(sp)", 0x50fe),
+    ("ld t0, 504(sp)", 0x72fe),
+    ("ld s0, 0(sp)", 0x6402),
+    ("ret", 0x8082),
+    ("jr t0", 0x8282),
+    ("mv a0, t1", 0x851a),
+    ("jalr t0", 0x9282),
+    ("add s0, s0, t0", 0x9416),
+    ("sw ra, 252(sp)", 0xdf86),
+    ("sd s1, 504(sp)", 0xffa6),
+];
+
+/// Instructions whose compressed encodings differ on RV32
+const COMPRESSED_RV32: &[(&str, u16)] = &[
+    ("srli s0, s0, 31", 0x807d),
+    ("slli a0, a0, 1", 0x0506),
+    ("lw a0, 4(s1)", 0x40c8),
+    ("sw ra, 252(sp)", 0xdf86),
+];
+
+/// Jumps and branches, whose offsets cannot be written in assembly without labels
+const JUMPS: &[(Inst, u32)] = &[
+    (
+        Inst::Jal {
+            rd: Register::RA,
+            offset: 2048,
+        },
+        0x0010_00ef,
+    ),
+    (
+        Inst::Jal {
+            rd: Register::ZERO,
+            offset: -4,
+        },
+        0xffdf_f06f,
+    ),
+    (
+        Inst::Branch {
+            op: BranchOp::Beq,
+            rs1: Register::A0,
+            rs2: Register::ZERO,
+            offset: 8,
+        },
+        0x0005_0463,
+    ),
+    (
+        Inst::Branch {
+            op: BranchOp::Bne,
+            rs1: Register(8),
+            rs2: Register(9),
+            offset: -4096,
+        },
+        0x8094_1063,
+    ),
+    (
+        Inst::Branch {
+            op: BranchOp::Bltu,
+            rs1: Register(5),
+            rs2: Register::T1,
+            offset: 4094,
+        },
+        0x7e62_efe3,
+    ),
+    (
+        Inst::Branch {
+            op: BranchOp::Bgeu,
+            rs1: Register(15),
+            rs2: Register(14),
+            offset: -2,
+        },
+        0xfee7_ffe3,
+    ),
+];
+
+/// The instruction words of an assembled program's text section
+fn assemble(source: &str, xlen: Xlen) -> Vec<u32> {
+    let program = riscv_sim::assemble(source, xlen).expect("failed to assemble");
+    program.image[..program.text_size as usize]
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn assembled_instructions() {
+    for (xlen, table) in [(Xlen::Rv32, RV32), (Xlen::Rv64, RV32), (Xlen::Rv64, RV64)] {
+        for (source, expected) in table {
+            let words = assemble(source, xlen);
+            assert_eq!(
+                words,
+                [*expected],
+                "`{}` on {:?}: found {:#010x}",
+                source,
+                xlen,
+                words[0]
+            );
+
+            let inst = isa::decode(*expected, xlen).expect("failed to decode");
+            assert_eq!(isa::encode(inst, xlen), Ok(*expected), "`{}`", source);
+        }
+    }
+}
+
+/// The halfwords of an assembled program's text section
+fn assemble_compressed(source: &str, xlen: Xlen) -> Vec<u16> {
+    let source = format!(".option rvc\n{}", source);
+    let program = riscv_sim::assemble(&source, xlen).expect("failed to assemble");
+    program.image[..program.text_size as usize]
+        .chunks_exact(2)
+        .map(|half| u16::from_le_bytes(half.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn compressed_instructions() {
+    for (xlen, table) in [(Xlen::Rv64, COMPRESSED_RV64), (Xlen::Rv32, COMPRESSED_RV32)] {
+        for (source, expected) in table {
+            let halves = assemble_compressed(source, xlen);
+            assert_eq!(
+                halves,
+                [*expected],
+                "`{}` on {:?}: found {:#06x}",
+                source,
+                xlen,
+                halves[0]
+            );
+
+            let inst = isa::expand(*expected, xlen).expect("failed to expand");
+            assert_eq!(isa::compress(inst, xlen), Some(*expected), "`{}`", source);
+        }
+    }
+
+    // Jumps and branches at the ends of their ranges, and `c.jal`, which only exists on RV32
+    for (xlen, inst, expected) in [
+        (
+            Xlen::Rv64,
+            Inst::Jal {
+                rd: Register::ZERO,
+                offset: -2048,
+            },
+            0xb001,
+        ),
+        (
+            Xlen::Rv64,
+            Inst::Jal {
+                rd: Register::ZERO,
+                offset: 2046,
+            },
+            0xaffd,
+        ),
+        (
+            Xlen::Rv32,
+            Inst::Jal {
+                rd: Register::RA,
+                offset: 2046,
+            },
+            0x2ffd,
+        ),
+        (
+            Xlen::Rv32,
+            Inst::Jal {
+                rd: Register::RA,
+                offset: -2,
+            },
+            0x3ffd,
+        ),
+        (
+            Xlen::Rv64,
+            Inst::Branch {
+                op: BranchOp::Beq,
+                rs1: Register(9),
+                rs2: Register::ZERO,
+                offset: -256,
+            },
+            0xd081,
+        ),
+        (
+            Xlen::Rv64,
+            Inst::Branch {
+                op: BranchOp::Bne,
+                rs1: Register(15),
+                rs2: Register::ZERO,
+                offset: 254,
+            },
+            0xeffd,
+        ),
+    ] {
+        assert_eq!(isa::compress(inst, xlen), Some(expected), "{:?}", inst);
+        assert_eq!(isa::expand(expected, xlen), Some(inst));
+    }
+
+    // Instructions without a compressed form, because of their registers or immediates
+    for source in [
+        "lbu s1, 0(s0)",
+        "addi a0, a1, 1",
+        "addi s0, s0, 32",
+        "ld a0, 4(s1)",
+        "ld t0, 8(t1)",
+        "sub a0, a0, t0",
+        "mul a0, a0, a1",
+    ] {
+        let halves = assemble_compressed(source, Xlen::Rv64);
+        assert_eq!(halves.len(), 2, "`{}` was compressed", source);
+    }
+
+    // The all-zero halfword is reserved, as is `c.addi4spn` with a zero immediate
+    assert_eq!(isa::expand(0x0000, Xlen::Rv64), None);
+    assert_eq!(isa::expand(0x0008, Xlen::Rv64), None);
+}
+
+#[test]
+fn compressed_branch_relaxation() {
+    // Branches start compressed, and are lengthened when their target is out of range
+    let source = |nops: usize| {
+        format!(
+            ".option rvc\nstart: beqz s1, end\n{}end: j start",
+            "nop\n".repeat(nops)
+        )
+    };
+    let near = source(100);
+    let program = riscv_sim::assemble(&near, Xlen::Rv64).unwrap();
+    assert_eq!(program.text_size, 2 + 100 * 2 + 2);
+    assert_eq!(program.compressed, 102);
+
+    let far = source(200);
+    let program = riscv_sim::assemble(&far, Xlen::Rv64).unwrap();
+    assert_eq!(program.text_size, 4 + 200 * 2 + 2);
+    let first = u32::from_le_bytes(program.image[..4].try_into().unwrap());
+    assert_eq!(
+        isa::decode(first, Xlen::Rv64),
+        Some(Inst::Branch {
+            op: BranchOp::Beq,
+            rs1: Register(9),
+            rs2: Register::ZERO,
+            offset: 4 + 200 * 2,
+        })
+    );
+
+    // Without `.option rvc`, nothing is compressed
+    let program = riscv_sim::assemble(&far.replace(".option rvc", ""), Xlen::Rv64).unwrap();
+    assert_eq!(program.compressed, 0);
+    assert_eq!(program.text_size, 4 + 200 * 4 + 4);
+}
+
+#[test]
+fn jumps_and_branches() {
+    for (inst, expected) in JUMPS {
+        assert_eq!(isa::encode(*inst, Xlen::Rv32), Ok(*expected), "{:?}", inst);
+        assert_eq!(isa::decode(*expected, Xlen::Rv32), Some(*inst));
+    }
+}
+
+#[test]
+fn unencodable_instructions() {
+    // Instructions that only exist on RV64, immediates that are too large, and offsets that
+    // are not a multiple of 2
+    let instructions = [
+        (
+            Xlen::Rv32,
+            Inst::Load {
+                width: isa::Width::Double,
+                rd: Register::A0,
+                rs1: Register::SP,
+                offset: 0,
+            },
+        ),
+        (
+            Xlen::Rv32,
+            Inst::OpImm {
+                op: isa::AluOp::Sll,
+                rd: Register::A0,
+                rs1: Register::A0,
+                imm: 32,
+                word: false,
+            },
+        ),
+        (
+            Xlen::Rv64,
+            Inst::OpImm {
+                op: isa::AluOp::Add,
+                rd: Register::A0,
+                rs1: Register::A0,
+                imm: 2048,
+                word: false,
+            },
+        ),
+        (
+            Xlen::Rv64,
+            Inst::Branch {
+                op: BranchOp::Beq,
+                rs1: Register::A0,
+                rs2: Register::ZERO,
+                offset: 3,
+            },
+        ),
+    ];
+    for (xlen, inst) in instructions {
+        assert!(isa::encode(inst, xlen).is_err(), "{:?}", inst);
+    }
+
+    // Reserved encodings, such as `srai` with bits set outside of funct7 and the shift amount
+    assert_eq!(isa::decode(0x0000_0000, Xlen::Rv64), None);
+    assert_eq!(isa::decode(0x6000_5013, Xlen::Rv64), None);
+    assert_eq!(isa::decode(0x0200_1013, Xlen::Rv32), None);
+}
+
+#[test]
+fn labels() {
+    let pending = |target: &str| Pending::Branch {
+        op: BranchOp::Beq,
+        rs1: Register::A0,
+        rs2: Register::ZERO,
+        target: target.to_string(),
+    };
+    let code = [
+        pending("end"),
+        Pending::Ready(Inst::OpImm {
+            op: isa::AluOp::Add,
+            rd: Register::A0,
+            rs1: Register::A0,
+            imm: -1,
+            word: false,
+        }),
+        Pending::Jal {
+            rd: Register::ZERO,
+            target: "start".to_string(),
+        },
+        Pending::PcRelHigh {
+            rd: Register::A1,
+            target: "data".to_string(),
+        },
+        Pending::PcRelLow {
+            jump: false,
+            rd: Register::A1,
+            rs1: Register::A1,
+            target: "data".to_string(),
+        },
+    ];
+
+    let mut symbols = HashMap::new();
+    symbols.insert("start".to_string(), TEXT_BASE);
+    symbols.insert("end".to_string(), TEXT_BASE + 12);
+    symbols.insert("data".to_string(), TEXT_BASE + 0x1000);
+    let words: Vec<u32> = code
+        .iter()
+        .enumerate()
+        .map(|(index, pending)| {
+            let pc = TEXT_BASE + index as u64 * 4;
+            isa::encode(pending.resolve(pc, &symbols).unwrap(), Xlen::Rv64).unwrap()
+        })
+        .collect();
+
+    // The same code assembled from source, where `data` is the start of the data section
+    let source = "start: beqz a0, end\naddi a0, a0, -1\nj start\nend: la a1, data\n\
+                  .data\ndata: .byte 1";
+    assert_eq!(words, assemble(source, Xlen::Rv64));
+    assert_eq!(words[0], 0x0005_0663);
+    assert_eq!(words[2], 0xff9f_f06f);
+
+    assert_eq!(
+        pending("missing").resolve(TEXT_BASE, &symbols),
+        Err(EncodeError::UndefinedLabel("missing".to_string()))
+    );
+
+    // Branches reach 4 KiB backwards, but 2 bytes less forwards
+    symbols.insert("far".to_string(), TEXT_BASE + 4096);
+    assert_eq!(
+        pending("far").resolve(TEXT_BASE, &symbols),
+        Err(EncodeError::BranchOutOfRange("far".to_string()))
+    );
+    assert!(pending("far").resolve(TEXT_BASE + 2, &symbols).is_ok());
+    assert!(pending("start").resolve(TEXT_BASE + 4096, &symbols).is_ok());
+}
+
+#[test]
+fn arch_attribute() {
+    for (isa, name) in [
+        ("rv32i", "rv32i"),
+        ("rv64gc", "rv64gc"),
+        ("rv64imafdc", "rv64gc"),
+        ("rv32i2p1_m2p0_zicsr2p0", "rv32im"),
+        ("RV64IC", "rv64ic"),
+    ] {
+        assert_eq!(
+            Arch::parse(isa).map(Arch::name),
+            Some(name.to_string()),
+            "{}",
+            isa
+        );
+    }
+    assert_eq!(Arch::parse("rv128i"), None);
+    assert_eq!(Arch::from_name("rv64imafdc"), None);
+
+    // Instructions outside of the ISA in the attribute are rejected, as are other register widths
+    let source = ".attribute arch, \"rv32i\"\nmul a0, a1, a2";
+    assert!(riscv_sim::assemble(source, Xlen::Rv32).is_err());
+    assert_eq!(
+        assemble(&source.replace("rv32i", "rv32im"), Xlen::Rv32),
+        [0x02c5_8533]
+    );
+    assert!(riscv_sim::assemble(".attribute 5, \"rv64i\"", Xlen::Rv32).is_err());
+}
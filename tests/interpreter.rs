@@ -0,0 +1,94 @@
+use brainfuck_riscv::{
+    dialect::{CellWidth, Dialect, EofBehavior},
+    interpreter::{self, InterpretError, Interpreter},
+    optimizer::{OptLevel, PassManager},
+    parser::parse,
+};
+
+/// Interpret a program before and after optimization, checking that both give the same output
+fn run(source: &str, dialect: Dialect, input: &[u8]) -> Vec<u8> {
+    let program = parse(source).unwrap();
+    let output = interpreter::run(&program, dialect, input).unwrap();
+    let optimized = PassManager::new(OptLevel::O2).run(&program);
+    assert_eq!(
+        interpreter::run(&optimized, dialect, input).unwrap(),
+        output,
+        "{}",
+        source
+    );
+    output
+}
+
+#[test]
+fn programs() {
+    let dialect = Dialect::default();
+    assert_eq!(run("++++++[>++++++++<-]>+.+.", dialect, b""), b"12");
+    assert_eq!(run(",[.[-],]", dialect, b"cat"), b"cat");
+    assert_eq!(run(">,[>,]<[.<]", dialect, b"abc"), b"cba");
+    assert_eq!(run("+>+>+<<[>]+++.", dialect, b""), [3]);
+}
+
+#[test]
+fn cell_widths() {
+    // Adding 256 only wraps 8-bit cells back to zero, and decrementing zero wraps to the
+    // largest value, whose lowest byte is written
+    let source = format!("{}[>+<[-]]>.[-]-.", "+".repeat(256));
+    for (bits, output) in [(8, 0), (16, 1), (32, 1), (64, 1)] {
+        let dialect = Dialect {
+            cell_width: CellWidth::from_bits(bits).unwrap(),
+            ..Dialect::default()
+        };
+        assert_eq!(run(&source, dialect, b""), [output, 255], "{} bits", bits);
+    }
+}
+
+#[test]
+fn eof_behaviors() {
+    for (eof, output) in [
+        (EofBehavior::Unchanged, [1, b'b']),
+        (EofBehavior::Zero, [0, 0]),
+        (EofBehavior::MinusOne, [255, 255]),
+    ] {
+        let dialect = Dialect {
+            eof,
+            ..Dialect::default()
+        };
+        assert_eq!(run("+,.", dialect, b""), output[..1], "{:?}", eof);
+        assert_eq!(run(",,,.", dialect, b"ab"), output[1..], "{:?}", eof);
+    }
+}
+
+#[test]
+fn tape_bounds() {
+    let dialect = Dialect {
+        tape_length: 4,
+        tape_start: 2,
+        ..Dialect::default()
+    };
+    assert_eq!(run("<<+.>>>+.", dialect, b""), [1, 1]);
+
+    // The pointer may leave the tape, but accessing a cell there is an error
+    assert_eq!(run("<<<>+.", dialect, b""), [1]);
+    let program = parse("<<<+").unwrap();
+    assert!(matches!(
+        interpreter::run(&program, dialect, b""),
+        Err(InterpretError::Underflow(1))
+    ));
+    let program = parse(">>.").unwrap();
+    let error = interpreter::run(&program, dialect, b"").unwrap_err();
+    assert_eq!(error.to_string(), "pointer overflow at instruction 1");
+}
+
+#[test]
+fn step_limit() {
+    let program = parse("+[]").unwrap();
+    let mut interpreter = Interpreter::new(&program, Dialect::default());
+    interpreter.step_limit = Some(1000);
+    let error = interpreter.run(&mut &b""[..], &mut Vec::new()).unwrap_err();
+    assert!(
+        matches!(error, InterpretError::StepLimit(1000)),
+        "{}",
+        error
+    );
+    assert_eq!(interpreter.steps, 1000);
+}
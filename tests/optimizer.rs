@@ -0,0 +1,357 @@
+use std::{cell::RefCell, rc::Rc};
+
+use brainfuck_riscv::{
+    instruction::Instruction::{self, *},
+    optimizer::{
+        clear_loops, defer_pointer_moves, link_loops, multiply_loops, optimize, scan_loops,
+        OptLevel, Pass, PassManager, UnknownPass,
+    },
+    parser::parse,
+};
+
+/// The cell value that decrementing once adds
+const MINUS_ONE: u64 = u64::MAX;
+
+/// Run a single pass function over the parsed source, relinking its loops
+fn run(pass: fn(&[Instruction]) -> Vec<Instruction>, source: &str) -> Vec<Instruction> {
+    let mut program = pass(&parse(source).unwrap());
+    link_loops(&mut program);
+    program
+}
+
+/// Assert that the pass leaves the program unchanged
+fn unchanged(pass: fn(&[Instruction]) -> Vec<Instruction>, source: &str) {
+    assert_eq!(run(pass, source), parse(source).unwrap(), "`{}`", source);
+}
+
+#[test]
+fn clear_loop() {
+    let clear = SetByte {
+        offset: 0,
+        value: 0,
+    };
+    assert_eq!(run(clear_loops, "[-]"), [clear]);
+    assert_eq!(run(clear_loops, "[+++]"), [clear]);
+
+    // Changes just before the loop are dropped, and changes just after are folded in
+    assert_eq!(
+        run(clear_loops, ">++[-]+++"),
+        [
+            AddPtr { count: 1 },
+            SetByte {
+                offset: 0,
+                value: 3
+            }
+        ]
+    );
+
+    // Only the innermost loop is a clear loop
+    assert_eq!(
+        run(clear_loops, "[[-]]"),
+        [LoopStart { end: 2 }, clear, LoopEnd { start: 0 }]
+    );
+
+    // An even step may never reach zero, and other commands in the loop change other cells
+    unchanged(clear_loops, "[--]");
+    unchanged(clear_loops, "[->]");
+    unchanged(clear_loops, "[-.]");
+}
+
+#[test]
+fn multiply_loop() {
+    assert_eq!(
+        run(multiply_loops, "[->+>+++<<]"),
+        [
+            MulAdd {
+                offset: 1,
+                factor: 1
+            },
+            MulAdd {
+                offset: 2,
+                factor: 3
+            },
+            SetByte {
+                offset: 0,
+                value: 0
+            },
+        ]
+    );
+
+    // Incrementing the counter until it wraps negates the factors
+    assert_eq!(
+        run(multiply_loops, "[<-->+]"),
+        [
+            MulAdd {
+                offset: -1,
+                factor: 2
+            },
+            SetByte {
+                offset: 0,
+                value: 0
+            },
+        ]
+    );
+
+    // A loop around another loop is not a multiply loop, although the inner loop can be
+    assert_eq!(
+        run(multiply_loops, "[->[-]<]"),
+        [
+            LoopStart { end: 5 },
+            AddByte {
+                offset: 0,
+                delta: MINUS_ONE
+            },
+            AddPtr { count: 1 },
+            SetByte {
+                offset: 0,
+                value: 0
+            },
+            SubPtr { count: 1 },
+            LoopEnd { start: 0 },
+        ]
+    );
+
+    // Unbalanced pointer moves, I/O, and counter steps other than one
+    unchanged(multiply_loops, "[->+]");
+    unchanged(multiply_loops, "[->.<]");
+    unchanged(multiply_loops, "[-->+<]");
+    unchanged(multiply_loops, "[>+<]");
+}
+
+#[test]
+fn scan_loop() {
+    assert_eq!(run(scan_loops, "[>]"), [Scan { stride: 1 }]);
+    assert_eq!(run(scan_loops, "[<<]"), [Scan { stride: -2 }]);
+    assert_eq!(
+        run(scan_loops, "[[>>>]]"),
+        [
+            LoopStart { end: 2 },
+            Scan { stride: 3 },
+            LoopEnd { start: 0 }
+        ]
+    );
+
+    // Loops that change cells, or whose moves are not combined into one
+    unchanged(scan_loops, "[>+]");
+    unchanged(scan_loops, "[-<]");
+    unchanged(scan_loops, "[><]");
+}
+
+#[test]
+fn pointer_moves() {
+    // Moves are deferred until an instruction that uses the pointer, and cancel out
+    assert_eq!(
+        run(defer_pointer_moves, ">+>-<<.>>>,"),
+        [
+            AddByte {
+                offset: 1,
+                delta: 1
+            },
+            AddByte {
+                offset: 2,
+                delta: MINUS_ONE
+            },
+            Write { count: 1 },
+            AddPtr { count: 3 },
+            Read { count: 1 },
+        ]
+    );
+
+    // Operations on the same cell in a region are combined
+    assert_eq!(
+        run(defer_pointer_moves, "+>+<+>+<<[-]"),
+        [
+            AddByte {
+                offset: 0,
+                delta: 2
+            },
+            AddByte {
+                offset: 1,
+                delta: 2
+            },
+            SubPtr { count: 1 },
+            LoopStart { end: 5 },
+            AddByte {
+                offset: 0,
+                delta: MINUS_ONE
+            },
+            LoopEnd { start: 3 },
+        ]
+    );
+
+    // Loops start a new region, so cells are not combined across them
+    assert_eq!(
+        run(defer_pointer_moves, "<+[>]+"),
+        [
+            AddByte {
+                offset: -1,
+                delta: 1
+            },
+            SubPtr { count: 1 },
+            LoopStart { end: 4 },
+            AddPtr { count: 1 },
+            LoopEnd { start: 2 },
+            AddByte {
+                offset: 0,
+                delta: 1
+            },
+        ]
+    );
+}
+
+#[test]
+fn levels_and_names() {
+    assert!(PassManager::new(OptLevel::O0).enabled().is_empty());
+    assert_eq!(
+        PassManager::new(OptLevel::O1).enabled(),
+        ["scan-loops", "clear-loops"]
+    );
+    assert_eq!(
+        PassManager::new(OptLevel::O2).enabled(),
+        [
+            "scan-loops",
+            "multiply-loops",
+            "clear-loops",
+            "defer-pointer-moves"
+        ]
+    );
+
+    // Enabling and disabling keeps the passes in their usual order
+    let mut passes = PassManager::new(OptLevel::O1);
+    passes.enable("defer-pointer-moves").unwrap();
+    passes.disable("scan-loops").unwrap();
+    passes.enable("multiply-loops").unwrap();
+    assert_eq!(
+        passes.enabled(),
+        ["multiply-loops", "clear-loops", "defer-pointer-moves"]
+    );
+
+    assert_eq!(
+        passes.enable("loop-unrolling"),
+        Err(UnknownPass("loop-unrolling".to_string()))
+    );
+    assert_eq!(
+        passes.disable("").unwrap_err().to_string(),
+        "unknown pass ``, expected one of: scan-loops, multiply-loops, clear-loops, \
+         defer-pointer-moves"
+    );
+}
+
+#[test]
+fn disabled_passes() {
+    let program = parse("[-]>[>]").unwrap();
+    assert_eq!(PassManager::new(OptLevel::O0).run(&program), program);
+
+    let mut passes = PassManager::new(OptLevel::O0);
+    passes.enable("scan-loops").unwrap();
+    assert_eq!(
+        passes.run(&program),
+        [
+            LoopStart { end: 2 },
+            AddByte {
+                offset: 0,
+                delta: MINUS_ONE
+            },
+            LoopEnd { start: 0 },
+            AddPtr { count: 1 },
+            Scan { stride: 1 },
+        ]
+    );
+
+    // With clear-loops disabled, multiply-loops still clears `[-]`, which has no other cells
+    let mut passes = PassManager::new(OptLevel::O2);
+    passes.disable("clear-loops").unwrap();
+    assert_eq!(
+        passes.run(&parse("[-]").unwrap()),
+        [SetByte {
+            offset: 0,
+            value: 0
+        }]
+    );
+}
+
+/// A pass that records its name each time it runs, and appends a write to the program until
+/// it has run `limit` times
+struct Recorder {
+    name: &'static str,
+    log: Rc<RefCell<Vec<&'static str>>>,
+    limit: usize,
+}
+
+impl Pass for Recorder {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn level(&self) -> OptLevel {
+        OptLevel::O0
+    }
+
+    fn run(&self, program: &[Instruction]) -> Vec<Instruction> {
+        let mut log = self.log.borrow_mut();
+        log.push(self.name);
+        let mut program = program.to_vec();
+        if log.iter().filter(|name| **name == self.name).count() <= self.limit {
+            program.push(Write { count: 1 });
+        }
+        program
+    }
+}
+
+#[test]
+fn ordering_and_fixpoint() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let recorder = |name, limit| {
+        Box::new(Recorder {
+            name,
+            log: log.clone(),
+            limit,
+        })
+    };
+
+    // Passes run in the order they were added, until an iteration changes nothing
+    let mut passes = PassManager::new(OptLevel::O0);
+    passes.add_pass(recorder("first", 2));
+    passes.add_pass(recorder("second", 0));
+    assert_eq!(passes.run(&[]), [Write { count: 1 }; 2]);
+    assert_eq!(
+        *log.borrow(),
+        ["first", "second", "first", "second", "first", "second"]
+    );
+    assert!(passes.disable("second").is_ok());
+
+    // A program that never stops changing is only run a limited number of times
+    log.borrow_mut().clear();
+    let mut passes = PassManager::new(OptLevel::O0);
+    passes.add_pass(recorder("forever", usize::MAX));
+    assert_eq!(
+        passes.run(&[]).len(),
+        PassManager::MAX_ITERATIONS,
+        "the passes did not stop"
+    );
+    assert_eq!(log.borrow().len(), PassManager::MAX_ITERATIONS);
+}
+
+#[test]
+fn scan_before_multiply_loop() {
+    // Replacing the scan loop moves the loop after it, whose targets must be relinked before
+    // multiply-loops reads them
+    assert_eq!(
+        optimize(&parse("[>]+[->+<]").unwrap()),
+        [
+            Scan { stride: 1 },
+            AddByte {
+                offset: 0,
+                delta: 1
+            },
+            MulAdd {
+                offset: 1,
+                factor: 1
+            },
+            SetByte {
+                offset: 0,
+                value: 0
+            },
+        ]
+    );
+}
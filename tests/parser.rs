@@ -0,0 +1,97 @@
+use brainfuck_riscv::{
+    instruction::Instruction,
+    parser::{parse, Bracket, Span, UnmatchedBracket},
+};
+
+/// The brackets, offsets, lines, and columns of the unmatched brackets in `source`
+fn unmatched(source: &str) -> Vec<(Bracket, usize, usize, usize)> {
+    parse(source)
+        .expect_err("expected a parse error")
+        .unmatched
+        .iter()
+        .map(|unmatched| {
+            let span = unmatched.span;
+            (unmatched.bracket, span.offset, span.line, span.column)
+        })
+        .collect()
+}
+
+#[test]
+fn well_formed_programs() {
+    assert_eq!(
+        parse("++>-<<[->.,]").unwrap(),
+        [
+            Instruction::AddByte {
+                offset: 0,
+                delta: 2
+            },
+            Instruction::AddPtr { count: 1 },
+            Instruction::AddByte {
+                offset: 0,
+                delta: u64::MAX
+            },
+            Instruction::SubPtr { count: 2 },
+            Instruction::LoopStart { end: 9 },
+            Instruction::AddByte {
+                offset: 0,
+                delta: u64::MAX
+            },
+            Instruction::AddPtr { count: 1 },
+            Instruction::Write { count: 1 },
+            Instruction::Read { count: 1 },
+            Instruction::LoopEnd { start: 4 },
+        ][..]
+    );
+
+    // Comments are skipped, and nested loops are matched with each other
+    assert_eq!(
+        parse("a [ b [ ] c ] d\n..").unwrap(),
+        [
+            Instruction::LoopStart { end: 3 },
+            Instruction::LoopStart { end: 2 },
+            Instruction::LoopEnd { start: 1 },
+            Instruction::LoopEnd { start: 0 },
+            Instruction::Write { count: 2 },
+        ][..]
+    );
+    assert_eq!(parse("no commands").unwrap(), []);
+}
+
+#[test]
+fn unmatched_brackets() {
+    assert_eq!(
+        unmatched("]["),
+        [(Bracket::Close, 0, 1, 1), (Bracket::Open, 1, 1, 2)]
+    );
+
+    // Every unmatched bracket is reported in source order, across lines, even though the
+    // unmatched `[` are only found at the end
+    assert_eq!(
+        unmatched("]\n+[]]\n [\n[-]["),
+        [
+            (Bracket::Close, 0, 1, 1),
+            (Bracket::Close, 5, 2, 4),
+            (Bracket::Open, 8, 3, 2),
+            (Bracket::Open, 13, 4, 4),
+        ]
+    );
+}
+
+#[test]
+fn non_ascii_source() {
+    // Offsets are in bytes, and columns in characters
+    let error = parse("é[\n→ ]]").unwrap_err();
+    assert_eq!(
+        error.unmatched,
+        [UnmatchedBracket {
+            bracket: Bracket::Close,
+            span: Span {
+                offset: 9,
+                len: 1,
+                line: 2,
+                column: 4,
+            },
+        }]
+    );
+    assert_eq!(error.to_string(), "unmatched `]` at line 2, column 4");
+}
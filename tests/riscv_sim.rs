@@ -0,0 +1,98 @@
+use brainfuck_riscv::{
+    isa::Register,
+    riscv_sim::{self, Machine, SimError, Xlen},
+};
+
+/// Run a program that exits with code 0, returning the value that it leaves in a1
+fn run_a1(source: &str, xlen: Xlen) -> u64 {
+    let program = riscv_sim::assemble(source, xlen).unwrap();
+    let mut machine = Machine::new(&program);
+    let code = machine
+        .run(&mut &b""[..], &mut Vec::new(), &mut Vec::new())
+        .unwrap();
+    assert_eq!(code, 0);
+    machine.register(Register::A1)
+}
+
+#[test]
+fn rars_calls() {
+    // Read a byte, write it in upper case, and exit with the RARS Exit call
+    let source = "main:\nli a7, 12\necall\naddi a0, a0, -32\nli a7, 11\necall\nli a7, 10\necall\n";
+    for xlen in [Xlen::Rv32, Xlen::Rv64] {
+        let output = riscv_sim::run(source, xlen, b"q").unwrap();
+        assert_eq!(output.stdout, b"Q");
+        assert_eq!(output.exit_code, 0);
+
+        // ReadChar returns -1 at the end of input
+        let output = riscv_sim::run(source, xlen, b"").unwrap();
+        assert_eq!(output.stdout, [(-1i8 - 32) as u8]);
+    }
+}
+
+#[test]
+fn linux_calls() {
+    let source = "\
+.data
+message: .ascii \"hi\\n\"
+.text
+.globl _start
+_start:
+li a0, 1
+la a1, message
+li a2, 3
+li a7, 64
+ecall
+li a0, 2
+la a1, message
+li a2, 2
+ecall
+li a0, 7
+li a7, 93
+ecall
+";
+    for xlen in [Xlen::Rv32, Xlen::Rv64] {
+        let output = riscv_sim::run(source, xlen, b"").unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+        assert_eq!(output.stderr, b"hi");
+        assert_eq!(output.exit_code, 7);
+    }
+
+    // System calls on other file descriptors fail with EBADF, and running past the last
+    // instruction exits with code 0
+    let source = "li a0, 5\nli a2, 1\nli a7, 64\necall\nmv a1, a0";
+    assert_eq!(run_a1(source, Xlen::Rv64), -9i64 as u64);
+}
+
+#[test]
+fn register_widths() {
+    let source = "li a0, -1\nsrli a1, a0, 1";
+    assert_eq!(run_a1(source, Xlen::Rv32), 0x7fff_ffff);
+    assert_eq!(run_a1(source, Xlen::Rv64), 0x7fff_ffff_ffff_ffff);
+
+    // RV32 registers hold sign-extended values
+    let source = "li a1, 0x40000000\nadd a1, a1, a1";
+    assert_eq!(run_a1(source, Xlen::Rv32), 0xffff_ffff_8000_0000);
+    assert_eq!(run_a1(source, Xlen::Rv64), 0x8000_0000);
+}
+
+#[test]
+fn errors() {
+    match riscv_sim::assemble("li a0, 1\naddi a0, a0\n", Xlen::Rv32) {
+        Err(SimError::Assemble { line: 2, .. }) => {}
+        result => panic!("expected an error on line 2, found {:?}", result),
+    }
+    assert!(riscv_sim::assemble("ld a0, 0(a1)", Xlen::Rv32).is_err());
+    assert!(riscv_sim::assemble("j missing", Xlen::Rv64).is_err());
+
+    let fault = riscv_sim::run("li a0, 0\nlw a1, 0(a0)", Xlen::Rv32, b"").unwrap_err();
+    assert!(matches!(fault, SimError::Fault { .. }), "{}", fault);
+
+    let program = riscv_sim::assemble("loop: j loop", Xlen::Rv64).unwrap();
+    let mut machine = Machine::new(&program);
+    machine.step_limit = Some(100);
+    let error = machine
+        .run(&mut &b""[..], &mut Vec::new(), &mut Vec::new())
+        .unwrap_err();
+    assert!(matches!(error, SimError::StepLimit(100)), "{}", error);
+    assert_eq!(machine.steps, 100);
+}